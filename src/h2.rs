@@ -0,0 +1,1687 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::Shutdown,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, Scope},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::{
+    hpack::{Decoder, Encoder, DEFAULT_TABLE_SIZE, HEADER_LIST_TOO_LARGE},
+    net::Stream,
+    parser::{insert_header, parse_content_length, ParseLimits},
+    request::parse_query,
+    Body, HttpRequest, HttpResponse, HttpStateCode, Method, Version,
+};
+
+/// 客户端连接序言(RFC 9113, 3.4)
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// 帧类型(RFC 9113, 6)
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// 帧标志，ACK只用于SETTINGS和PING，与END_STREAM取值相同
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// SETTINGS参数(RFC 9113, 6.5.2)
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+// 接收帧的最大长度，不修改SETTINGS_MAX_FRAME_SIZE的初始值
+const MAX_FRAME_SIZE: usize = 16384;
+const MAX_FRAME_SIZE_LIMIT: u32 = (1 << 24) - 1;
+/// 单个连接上同时处理的最大请求数
+const MAX_CONCURRENT_STREAMS: usize = 100;
+
+// 不允许出现在HTTP/2中的连接相关字段(RFC 9113, 8.2.2)
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// 错误码(RFC 9113, 7)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    CompressionError = 0x9,
+    EnhanceYourCalm = 0xb,
+}
+
+// 结束整个连接的错误
+#[derive(Debug)]
+enum Error {
+    Io(io::Error),
+    // 发送GOAWAY后关闭连接
+    Protocol(ErrorCode, &'static str),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    // 去掉PADDED标志对应的填充(RFC 9113, 6.1)
+    fn data(&self) -> Result<&[u8], Error> {
+        if self.flags & PADDED == 0 {
+            return Ok(&self.payload);
+        }
+        let (&pad, rest) = self.payload.split_first().ok_or(Error::Protocol(
+            ErrorCode::FrameSizeError,
+            "missing pad length",
+        ))?;
+        if pad as usize > rest.len() {
+            return Err(Error::Protocol(
+                ErrorCode::ProtocolError,
+                "padding too long",
+            ));
+        }
+        Ok(&rest[..rest.len() - pad as usize])
+    }
+
+    fn u32_at(&self, i: usize) -> u32 {
+        u32::from_be_bytes(self.payload[i..i + 4].try_into().unwrap())
+    }
+}
+
+// 发送方向的状态，由读取线程和请求处理线程共享
+#[derive(Debug)]
+struct State {
+    // 连接级别的发送窗口
+    window: i64,
+    initial_window: i64,
+    max_frame_size: usize,
+    // 未关闭的流，接收请求时创建，响应发送完成或被重置后删除
+    streams: HashMap<u32, StreamState>,
+    // 已接受的最大流ID，GOAWAY中告知客户端
+    last_stream_id: u32,
+    // 已发送GOAWAY或收到客户端的GOAWAY，不再接受新的流
+    going_away: bool,
+    goaway_sent: bool,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct StreamState {
+    window: i64,
+    reset: bool,
+    // 流关闭时归还
+    _permit: StreamPermit,
+}
+
+/// 所有HTTP/2连接同时处理的请求数上限，每个请求占用一个线程
+#[derive(Debug)]
+pub(crate) struct StreamLimit {
+    max: usize,
+    active: AtomicUsize,
+}
+
+impl StreamLimit {
+    pub(crate) fn new(max: usize) -> Self {
+        StreamLimit {
+            max,
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    // 达到上限时返回None
+    fn acquire(self: &Arc<Self>) -> Option<StreamPermit> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(StreamPermit(self.clone()))
+    }
+}
+
+#[derive(Debug)]
+struct StreamPermit(Arc<StreamLimit>);
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// 正在接收请求体的流
+struct Pending {
+    request: HttpRequest<'static>,
+    body: Vec<u8>,
+    content_length: Option<usize>,
+}
+
+/// 一个HTTP/2连接(RFC 9113)
+///
+/// 当前线程读取并处理帧，每个请求在独立的线程中调用处理函数，
+/// 响应按流量控制窗口分帧写出，多个请求的响应可以交错发送
+pub(crate) struct Connection<'a> {
+    stream: &'a Stream,
+    limits: ParseLimits,
+    max_requests: usize,
+    headers: &'a HashMap<String, String>,
+    limit: Arc<StreamLimit>,
+    // 写入帧时持有，保证帧不交错，同时保证头部块的编码顺序与发送顺序一致
+    writer: Mutex<Encoder>,
+    state: Mutex<State>,
+    // 发送窗口增大、流被重置或连接关闭
+    changed: Condvar,
+}
+
+impl<'a> Connection<'a> {
+    /// headers为每个响应默认携带的响应头，limit在所有连接之间共享，超出时以REFUSED_STREAM拒绝新的流
+    pub(crate) fn new(
+        stream: &'a Stream,
+        limits: ParseLimits,
+        max_requests: usize,
+        headers: &'a HashMap<String, String>,
+        limit: Arc<StreamLimit>,
+    ) -> Self {
+        Connection {
+            stream,
+            limits,
+            max_requests: max_requests.max(1),
+            headers,
+            limit,
+            writer: Mutex::new(Encoder::new()),
+            state: Mutex::new(State {
+                window: DEFAULT_WINDOW_SIZE,
+                initial_window: DEFAULT_WINDOW_SIZE,
+                max_frame_size: MAX_FRAME_SIZE,
+                streams: HashMap::new(),
+                last_stream_id: 0,
+                going_away: false,
+                goaway_sent: false,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// 处理连接直到关闭，reader应从连接序言开始
+    ///
+    /// upgrade为通过`Upgrade: h2c`升级的请求及其`HTTP2-Settings`，作为流1响应
+    pub(crate) fn serve<R, F>(
+        &self,
+        reader: R,
+        upgrade: Option<(HttpRequest<'static>, Vec<u8>)>,
+        service: &F,
+    ) where
+        R: Read,
+        F: Fn(&mut HttpRequest<'static>, &mut HttpResponse) + Sync,
+    {
+        // 帧通常较小，且窗口耗尽时需要等待WINDOW_UPDATE，不能被Nagle算法延迟
+        if let Err(e) = self.stream.set_nodelay(true) {
+            println!("set nodelay err: {}", e);
+        }
+        let mut reader = BufReader::new(reader);
+        thread::scope(|s| {
+            let result = self.run(s, &mut reader, upgrade, service);
+            let code = match result {
+                Ok(()) => ErrorCode::NoError,
+                Err(Error::Io(e)) => {
+                    println!("http2 io error: {}", e);
+                    ErrorCode::NoError
+                }
+                Err(Error::Protocol(code, reason)) => {
+                    println!("http2 connection error: {}", reason);
+                    code
+                }
+            };
+            if code != ErrorCode::NoError || !self.state().goaway_sent {
+                let _ = self.go_away(code);
+            }
+            // 不再读取WINDOW_UPDATE，等待发送窗口的请求无法继续
+            self.state().closed = true;
+            self.changed.notify_all();
+        });
+    }
+
+    fn run<'s, R, F>(
+        &'s self,
+        scope: &'s Scope<'s, '_>,
+        reader: &mut R,
+        upgrade: Option<(HttpRequest<'static>, Vec<u8>)>,
+        service: &'s F,
+    ) -> Result<(), Error>
+    where
+        R: BufRead,
+        F: Fn(&mut HttpRequest<'static>, &mut HttpResponse) + Sync,
+    {
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, self.limits.max_header_size),
+        ] {
+            settings.extend(id.to_be_bytes());
+            settings.extend((value as u32).to_be_bytes());
+        }
+        self.write_frame(SETTINGS, 0, 0, &settings)?;
+
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+        let mut receiving: HashMap<u32, Pending> = HashMap::new();
+        let mut last_stream_id = 0;
+        let mut served = 0;
+        if let Some((mut request, settings)) = upgrade {
+            self.apply_settings(&settings)?;
+            request.version = Version::V2_0;
+            last_stream_id = 1;
+            match self.limit.acquire() {
+                Some(permit) => {
+                    served += 1;
+                    self.open_stream(1, served, permit);
+                    self.dispatch(scope, 1, request, None, true, service);
+                }
+                None => self.reset_stream(1, ErrorCode::RefusedStream)?,
+            }
+        }
+
+        let mut preface = [0; PREFACE.len()];
+        reader.read_exact(&mut preface)?;
+        if preface != PREFACE {
+            return Err(Error::Protocol(
+                ErrorCode::ProtocolError,
+                "invalid connection preface",
+            ));
+        }
+        let mut settings_received = false;
+
+        loop {
+            {
+                let state = self.state();
+                if state.going_away && state.streams.is_empty() {
+                    return Ok(());
+                }
+            }
+            let frame = match Self::read_frame(reader) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(()),
+                // 空闲超时，仍有请求在处理时继续等待
+                Err(Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    match self.state().streams.is_empty() {
+                        true => return Ok(()),
+                        false => continue,
+                    }
+                }
+                Err(e) => return Err(e),
+            };
+            if !settings_received && frame.kind != SETTINGS {
+                return Err(Error::Protocol(
+                    ErrorCode::ProtocolError,
+                    "first frame is not SETTINGS",
+                ));
+            }
+            let id = frame.stream_id;
+
+            match frame.kind {
+                HEADERS => {
+                    if id == 0 {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "HEADERS on stream 0",
+                        ));
+                    }
+                    let end_stream = frame.flags & END_STREAM != 0;
+                    let block = self.read_header_block(reader, frame)?;
+                    // 头部列表超出上限时为None，只以431响应该流
+                    let fields = match decoder.decode(&block, self.limits.max_header_size) {
+                        Ok(fields) => Some(fields),
+                        Err(HEADER_LIST_TOO_LARGE) => None,
+                        Err(e) => {
+                            println!("{}", e);
+                            return Err(Error::Protocol(
+                                ErrorCode::CompressionError,
+                                "invalid header block",
+                            ));
+                        }
+                    };
+
+                    // 请求体之后的trailer
+                    if let Some(mut pending) = receiving.remove(&id) {
+                        let fields = match fields {
+                            Some(fields) if end_stream => fields,
+                            None if end_stream => {
+                                let code = HttpStateCode::StatusRequestHeaderFieldsTooLarge;
+                                self.dispatch(
+                                    scope,
+                                    id,
+                                    pending.request,
+                                    Some(code),
+                                    true,
+                                    service,
+                                );
+                                continue;
+                            }
+                            _ => {
+                                self.close_stream(id);
+                                self.reset_stream(id, ErrorCode::ProtocolError)?;
+                                continue;
+                            }
+                        };
+                        if fields.iter().any(|(k, _)| k.starts_with(':')) {
+                            self.close_stream(id);
+                            self.reset_stream(id, ErrorCode::ProtocolError)?;
+                            continue;
+                        }
+                        for (key, value) in fields.iter() {
+                            insert_header(&mut pending.request.headers, key, value);
+                        }
+                        self.finish_request(scope, id, pending, service)?;
+                        continue;
+                    }
+                    if id <= last_stream_id {
+                        if self.state().streams.contains_key(&id) {
+                            self.reset_stream(id, ErrorCode::StreamClosed)?;
+                            continue;
+                        }
+                        return Err(Error::Protocol(
+                            ErrorCode::StreamClosed,
+                            "HEADERS on closed stream",
+                        ));
+                    }
+                    if id.is_multiple_of(2) {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "stream id must be odd",
+                        ));
+                    }
+                    last_stream_id = id;
+
+                    let permit = {
+                        let state = self.state();
+                        match state.going_away || state.streams.len() >= MAX_CONCURRENT_STREAMS {
+                            true => None,
+                            false => self.limit.acquire(),
+                        }
+                    };
+                    let permit = match permit {
+                        Some(permit) => permit,
+                        None => {
+                            self.reset_stream(id, ErrorCode::RefusedStream)?;
+                            continue;
+                        }
+                    };
+                    let fields = match fields {
+                        Some(fields) => fields,
+                        // 请求头没有保留，无法得知请求方法，按GET响应
+                        None => {
+                            served += 1;
+                            self.open_stream(id, served, permit);
+                            let code = HttpStateCode::StatusRequestHeaderFieldsTooLarge;
+                            let request = HttpRequest {
+                                version: Version::V2_0,
+                                ..Default::default()
+                            };
+                            self.dispatch(scope, id, request, Some(code), end_stream, service);
+                            continue;
+                        }
+                    };
+                    let (request, content_length) = match Self::build_request(fields) {
+                        Ok(request) => request,
+                        Err(reason) => {
+                            println!("malformed http2 request: {}", reason);
+                            self.reset_stream(id, ErrorCode::ProtocolError)?;
+                            continue;
+                        }
+                    };
+                    served += 1;
+                    self.open_stream(id, served, permit);
+                    let pending = Pending {
+                        request,
+                        body: Vec::new(),
+                        content_length,
+                    };
+                    match end_stream {
+                        true => self.finish_request(scope, id, pending, service)?,
+                        false => {
+                            receiving.insert(id, pending);
+                        }
+                    }
+                }
+                DATA => {
+                    if id == 0 {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "DATA on stream 0",
+                        ));
+                    }
+                    // 流量控制按整个帧长度计算(包括填充)，收到后立即归还连接窗口
+                    let len = frame.payload.len();
+                    self.window_update(0, len)?;
+                    let data = frame.data()?;
+                    let pending = match receiving.get_mut(&id) {
+                        Some(pending) => pending,
+                        None if id > last_stream_id => {
+                            return Err(Error::Protocol(
+                                ErrorCode::ProtocolError,
+                                "DATA on idle stream",
+                            ))
+                        }
+                        None => {
+                            // 请求已接收完整，或已经提前响应并重置的流
+                            if self.state().streams.contains_key(&id) {
+                                self.reset_stream(id, ErrorCode::StreamClosed)?;
+                            }
+                            continue;
+                        }
+                    };
+                    if data.len() > self.limits.max_body_size - pending.body.len() {
+                        let pending = receiving.remove(&id).unwrap();
+                        let code = HttpStateCode::StatusRequestEntityTooLarge;
+                        self.dispatch(scope, id, pending.request, Some(code), false, service);
+                        continue;
+                    }
+                    pending.body.extend_from_slice(data);
+                    if frame.flags & END_STREAM != 0 {
+                        let pending = receiving.remove(&id).unwrap();
+                        self.finish_request(scope, id, pending, service)?;
+                    } else {
+                        self.window_update(id, len)?;
+                    }
+                }
+                PRIORITY => {
+                    if id == 0 {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "PRIORITY on stream 0",
+                        ));
+                    }
+                    if frame.payload.len() != 5 {
+                        self.reset_stream(id, ErrorCode::FrameSizeError)?;
+                    }
+                }
+                RST_STREAM => {
+                    if id == 0 || id > last_stream_id {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "RST_STREAM on idle stream",
+                        ));
+                    }
+                    if frame.payload.len() != 4 {
+                        return Err(Error::Protocol(
+                            ErrorCode::FrameSizeError,
+                            "invalid RST_STREAM length",
+                        ));
+                    }
+                    if receiving.remove(&id).is_some() {
+                        self.close_stream(id);
+                    } else if let Some(stream) = self.state().streams.get_mut(&id) {
+                        stream.reset = true;
+                    }
+                    self.changed.notify_all();
+                }
+                SETTINGS => {
+                    if id != 0 {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "SETTINGS on stream",
+                        ));
+                    }
+                    if frame.flags & ACK != 0 {
+                        if !frame.payload.is_empty() {
+                            return Err(Error::Protocol(
+                                ErrorCode::FrameSizeError,
+                                "SETTINGS ack with payload",
+                            ));
+                        }
+                        continue;
+                    }
+                    self.apply_settings(&frame.payload)?;
+                    self.write_frame(SETTINGS, ACK, 0, &[])?;
+                    settings_received = true;
+                }
+                PING => {
+                    if id != 0 {
+                        return Err(Error::Protocol(ErrorCode::ProtocolError, "PING on stream"));
+                    }
+                    if frame.payload.len() != 8 {
+                        return Err(Error::Protocol(
+                            ErrorCode::FrameSizeError,
+                            "invalid PING length",
+                        ));
+                    }
+                    if frame.flags & ACK == 0 {
+                        self.write_frame(PING, ACK, 0, &frame.payload)?;
+                    }
+                }
+                GOAWAY => {
+                    if id != 0 {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "GOAWAY on stream",
+                        ));
+                    }
+                    // 客户端不再发起新的请求，处理完已接收的请求后关闭
+                    self.state().going_away = true;
+                }
+                WINDOW_UPDATE => {
+                    if frame.payload.len() != 4 {
+                        return Err(Error::Protocol(
+                            ErrorCode::FrameSizeError,
+                            "invalid WINDOW_UPDATE length",
+                        ));
+                    }
+                    let increment = (frame.u32_at(0) & 0x7fff_ffff) as i64;
+                    if id == 0 {
+                        if increment == 0 {
+                            return Err(Error::Protocol(
+                                ErrorCode::ProtocolError,
+                                "zero window increment",
+                            ));
+                        }
+                        let mut state = self.state();
+                        state.window += increment;
+                        if state.window > MAX_WINDOW_SIZE {
+                            return Err(Error::Protocol(
+                                ErrorCode::FlowControlError,
+                                "connection window overflow",
+                            ));
+                        }
+                    } else if id > last_stream_id {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "WINDOW_UPDATE on idle stream",
+                        ));
+                    } else {
+                        let mut state = self.state();
+                        let error = match state.streams.get_mut(&id) {
+                            Some(_) if increment == 0 => Some(ErrorCode::ProtocolError),
+                            Some(stream) => {
+                                stream.window += increment;
+                                (stream.window > MAX_WINDOW_SIZE)
+                                    .then_some(ErrorCode::FlowControlError)
+                            }
+                            None => None,
+                        };
+                        drop(state);
+                        if let Some(code) = error {
+                            receiving.remove(&id);
+                            self.reset_stream(id, code)?;
+                        }
+                    }
+                    self.changed.notify_all();
+                }
+                PUSH_PROMISE => {
+                    return Err(Error::Protocol(
+                        ErrorCode::ProtocolError,
+                        "PUSH_PROMISE from client",
+                    ))
+                }
+                CONTINUATION => {
+                    return Err(Error::Protocol(
+                        ErrorCode::ProtocolError,
+                        "unexpected CONTINUATION",
+                    ))
+                }
+                // 未知类型的帧必须忽略
+                _ => {}
+            }
+        }
+    }
+
+    // 在帧的边界上读取到EOF时返回None；
+    // 只有尚未读到帧的任何字节时才返回读超时，帧读到一半超时已读的数据无法恢复，视为连接出错
+    fn read_frame<R: BufRead>(reader: &mut R) -> Result<Option<Frame>, Error> {
+        let mut head = [0; 9];
+        let mut n = 0;
+        while n < head.len() {
+            match reader.read(&mut head[n..]) {
+                Ok(0) if n == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(m) => n += m,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if n == 0 => return Err(e.into()),
+                Err(e) => return Err(incomplete_frame(e)),
+            }
+        }
+        let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(Error::Protocol(
+                ErrorCode::FrameSizeError,
+                "frame too large",
+            ));
+        }
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).map_err(incomplete_frame)?;
+        Ok(Some(Frame {
+            kind: head[3],
+            flags: head[4],
+            stream_id: u32::from_be_bytes(head[5..].try_into().unwrap()) & 0x7fff_ffff,
+            payload,
+        }))
+    }
+
+    // 读取完整的头部块，HEADERS之后紧接着的CONTINUATION属于同一个头部块
+    fn read_header_block<R: BufRead>(
+        &self,
+        reader: &mut R,
+        frame: Frame,
+    ) -> Result<Vec<u8>, Error> {
+        let mut block = frame.data()?;
+        if frame.flags & PRIORITY_FLAG != 0 {
+            if block.len() < 5 {
+                return Err(Error::Protocol(
+                    ErrorCode::FrameSizeError,
+                    "HEADERS too short for priority",
+                ));
+            }
+            block = &block[5..];
+        }
+        let mut block = block.to_vec();
+        let mut flags = frame.flags;
+        while flags & END_HEADERS == 0 {
+            let next = Self::read_frame(reader)?.ok_or(Error::Protocol(
+                ErrorCode::ProtocolError,
+                "connection closed in header block",
+            ))?;
+            if next.kind != CONTINUATION || next.stream_id != frame.stream_id {
+                return Err(Error::Protocol(
+                    ErrorCode::ProtocolError,
+                    "expected CONTINUATION",
+                ));
+            }
+            // 压缩后的头部块不会比解码后的长度限制大太多
+            if block.len() + next.payload.len() > self.limits.max_header_size * 2 {
+                return Err(Error::Protocol(
+                    ErrorCode::EnhanceYourCalm,
+                    "header block too large",
+                ));
+            }
+            block.extend(next.payload);
+            flags = next.flags;
+        }
+        Ok(block)
+    }
+
+    // 校验并转换请求头(RFC 9113, 8.3)，同时返回content-length以便校验请求体长度
+    fn build_request(
+        fields: Vec<(String, String)>,
+    ) -> Result<(HttpRequest<'static>, Option<usize>), &'static str> {
+        let mut method = None;
+        let mut scheme = None;
+        let mut path = None;
+        let mut authority = None;
+        let mut headers = HashMap::new();
+        let mut cookies = Vec::new();
+        let mut content_length = None;
+        for (key, value) in fields {
+            if let Some(name) = key.strip_prefix(':') {
+                if !headers.is_empty() || !cookies.is_empty() {
+                    return Err("pseudo-header after regular header");
+                }
+                let slot = match name {
+                    "method" => &mut method,
+                    "scheme" => &mut scheme,
+                    "path" => &mut path,
+                    "authority" => &mut authority,
+                    _ => return Err("unknown pseudo-header"),
+                };
+                if slot.replace(value).is_some() {
+                    return Err("duplicate pseudo-header");
+                }
+                continue;
+            }
+            if key.is_empty() || key.bytes().any(|b| b.is_ascii_uppercase()) {
+                return Err("invalid header name");
+            }
+            if CONNECTION_HEADERS.contains(&key.as_str()) {
+                return Err("connection-specific header");
+            }
+            if key == "te" && value != "trailers" {
+                return Err("invalid te header");
+            }
+            if key == "content-length" {
+                let len = parse_content_length(&value).ok_or("invalid content-length")?;
+                if content_length.replace(len).is_some_and(|l| l != len) {
+                    return Err("conflicting content-length");
+                }
+            }
+            // 多个cookie字段合并时使用"; "分隔(RFC 9113, 8.2.3)
+            if key == "cookie" {
+                cookies.push(value);
+                continue;
+            }
+            insert_header(&mut headers, &key, &value);
+        }
+        if !cookies.is_empty() {
+            headers.insert("cookie".to_string(), cookies.join("; "));
+        }
+
+        let method = Method::from(method.ok_or("missing :method")?.as_str());
+        let target = match method {
+            // CONNECT请求只有:authority(RFC 9113, 8.5)
+            Method::CONNECT if scheme.is_none() && path.is_none() => {
+                authority.clone().ok_or("missing :authority")?
+            }
+            _ => {
+                scheme.ok_or("missing :scheme")?;
+                path.filter(|p| !p.is_empty()).ok_or("missing :path")?
+            }
+        };
+        if let Some(authority) = authority {
+            headers.entry("host".to_string()).or_insert(authority);
+        }
+        let (uri, params) = target.split_once('?').unwrap_or((&target, ""));
+        let request = HttpRequest {
+            method,
+            uri: uri.to_string(),
+            version: Version::V2_0,
+            headers,
+            params: Some(params.to_string()),
+            query: parse_query(params),
+            ..HttpRequest::default()
+        };
+        Ok((request, content_length))
+    }
+
+    fn apply_settings(&self, payload: &[u8]) -> Result<(), Error> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(Error::Protocol(
+                ErrorCode::FrameSizeError,
+                "invalid SETTINGS length",
+            ));
+        }
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes(setting[2..].try_into().unwrap());
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => {
+                    self.writer
+                        .lock()
+                        .unwrap()
+                        .set_max_table_size(value as usize);
+                }
+                SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(Error::Protocol(
+                        ErrorCode::ProtocolError,
+                        "invalid ENABLE_PUSH",
+                    ))
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW_SIZE {
+                        return Err(Error::Protocol(
+                            ErrorCode::FlowControlError,
+                            "invalid INITIAL_WINDOW_SIZE",
+                        ));
+                    }
+                    // 已打开的流按差值调整发送窗口(RFC 9113, 6.9.2)
+                    let mut state = self.state();
+                    let delta = value - state.initial_window;
+                    state.initial_window = value;
+                    for stream in state.streams.values_mut() {
+                        stream.window += delta;
+                        if stream.window > MAX_WINDOW_SIZE {
+                            return Err(Error::Protocol(
+                                ErrorCode::FlowControlError,
+                                "stream window overflow",
+                            ));
+                        }
+                    }
+                    self.changed.notify_all();
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(MAX_FRAME_SIZE as u32..=MAX_FRAME_SIZE_LIMIT).contains(&value) {
+                        return Err(Error::Protocol(
+                            ErrorCode::ProtocolError,
+                            "invalid MAX_FRAME_SIZE",
+                        ));
+                    }
+                    self.state().max_frame_size = value as usize;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // 达到单个连接的最大请求数后发送GOAWAY，不再接受新的流
+    fn open_stream(&self, id: u32, served: usize, permit: StreamPermit) {
+        let mut state = self.state();
+        let window = state.initial_window;
+        state.streams.insert(
+            id,
+            StreamState {
+                window,
+                reset: false,
+                _permit: permit,
+            },
+        );
+        state.last_stream_id = id;
+        if served >= self.max_requests && !state.going_away {
+            state.going_away = true;
+            drop(state);
+            let _ = self.go_away(ErrorCode::NoError);
+        }
+    }
+
+    fn close_stream(&self, id: u32) {
+        let mut state = self.state();
+        state.streams.remove(&id);
+        // 读取线程阻塞在等待新的帧上，关闭读方向使其退出
+        if state.going_away && state.streams.is_empty() && !state.closed {
+            let _ = self.stream.shutdown(Shutdown::Read);
+        }
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    fn finish_request<'s, F>(
+        &'s self,
+        scope: &'s Scope<'s, '_>,
+        id: u32,
+        mut pending: Pending,
+        service: &'s F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(&mut HttpRequest<'static>, &mut HttpResponse) + Sync,
+    {
+        if pending
+            .content_length
+            .is_some_and(|len| len != pending.body.len())
+        {
+            self.close_stream(id);
+            return self.reset_stream(id, ErrorCode::ProtocolError);
+        }
+        if !pending.body.is_empty() {
+            pending.request.body = Some(pending.body);
+        }
+        self.dispatch(scope, id, pending.request, None, true, service);
+        Ok(())
+    }
+
+    // 在新线程中生成并发送响应，status不为None时直接以该状态码响应，不调用处理函数；
+    // complete为false表示请求体尚未接收完整，响应后以NO_ERROR重置流
+    fn dispatch<'s, F>(
+        &'s self,
+        scope: &'s Scope<'s, '_>,
+        id: u32,
+        mut request: HttpRequest<'static>,
+        status: Option<HttpStateCode>,
+        complete: bool,
+        service: &'s F,
+    ) where
+        F: Fn(&mut HttpRequest<'static>, &mut HttpResponse) + Sync,
+    {
+        scope.spawn(move || {
+            let _guard = StreamGuard(self, id);
+            let response = || {
+                let mut resp = HttpResponse::new();
+                resp.version = Version::V2_0;
+                resp.headers.extend(self.headers.clone());
+                resp
+            };
+            let mut resp = response();
+            match status {
+                Some(code) => {
+                    resp.set_http_state_code(code);
+                }
+                None => {
+                    // 处理函数panic时以500响应，不影响连接上的其他流
+                    let call = || service(&mut request, &mut resp);
+                    if panic::catch_unwind(AssertUnwindSafe(call)).is_err() {
+                        println!("http2 stream {} handler panicked", id);
+                        resp = response();
+                        resp.set_http_state_code(HttpStateCode::StatusInternalServerError);
+                    }
+                }
+            }
+            let with_body = request.method != Method::HEAD;
+            // 响应体由处理函数提供，发送过程中panic时响应头可能已经发出，只能重置流
+            let send = || self.send_response(id, resp, with_body);
+            let result = panic::catch_unwind(AssertUnwindSafe(send))
+                .unwrap_or_else(|_| {
+                    println!("http2 stream {} response body panicked", id);
+                    self.reset_stream(id, ErrorCode::InternalError)
+                })
+                .and_then(|_| match complete {
+                    true => Ok(()),
+                    false => self.reset_stream(id, ErrorCode::NoError),
+                });
+            if let Err(e) = result {
+                println!("http2 stream {} error: {:?}", id, e);
+            }
+        });
+    }
+
+    fn send_response(&self, id: u32, mut resp: HttpResponse, with_body: bool) -> Result<(), Error> {
+        let body = resp.take_body();
+        let content_length = resp.content_length(&body);
+        let mut fields = vec![(":status".to_string(), resp.status_code.to_string())];
+        for (key, value) in resp.headers {
+            let key = key.to_ascii_lowercase();
+            if key == "content-length" || CONNECTION_HEADERS.contains(&key.as_str()) {
+                continue;
+            }
+            fields.push((key, value));
+        }
+        // 与HTTP/1相同，204及未显式设置的304不携带content-length
+        if let Some(len) = content_length {
+            fields.push(("content-length".to_string(), len));
+        }
+        let len = body.len();
+        let end = !with_body || len == Some(0);
+        self.write_headers(id, &fields, end)?;
+        if end {
+            return Ok(());
+        }
+
+        match body {
+            Body::Empty => self.write_data(id, &[], true).map(|_| ()),
+            Body::Bytes(bytes) => self.write_data(id, &bytes, true).map(|_| ()),
+            Body::Reader(reader, len) => {
+                let mut reader = reader.take(len.unwrap_or(u64::MAX));
+                let mut buf = vec![0; MAX_FRAME_SIZE];
+                let mut sent = 0;
+                loop {
+                    let n = match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => n,
+                        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            self.reset_stream(id, ErrorCode::InternalError)?;
+                            return Err(e.into());
+                        }
+                    };
+                    sent += n as u64;
+                    if !self.write_data(id, &buf[..n], false)? {
+                        return Ok(());
+                    }
+                }
+                // 实际长度与Content-Length不一致时不能正常结束流
+                if len.is_some_and(|len| sent < len) {
+                    return self.reset_stream(id, ErrorCode::InternalError);
+                }
+                self.write_data(id, &[], true).map(|_| ())
+            }
+            Body::Chunks(chunks) => {
+                for chunk in chunks {
+                    if !self.write_data(id, &chunk, false)? {
+                        return Ok(());
+                    }
+                }
+                self.write_data(id, &[], true).map(|_| ())
+            }
+        }
+    }
+
+    fn write_headers(&self, id: u32, fields: &[(String, String)], end: bool) -> Result<(), Error> {
+        let max_frame_size = self.state().max_frame_size;
+        let mut writer = self.writer.lock().unwrap();
+        let block = writer.encode(fields.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        // 超出最大帧长度的部分通过CONTINUATION发送，中间不能插入其他帧
+        let chunks: Vec<&[u8]> = block.chunks(max_frame_size).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let (kind, mut flags) = match i {
+                0 if end => (HEADERS, END_STREAM),
+                0 => (HEADERS, 0),
+                _ => (CONTINUATION, 0),
+            };
+            if i == chunks.len() - 1 {
+                flags |= END_HEADERS;
+            }
+            Self::write_raw(self.stream, kind, flags, id, chunk)?;
+        }
+        Ok(())
+    }
+
+    // 按发送窗口分帧写出DATA，窗口不足时等待WINDOW_UPDATE；流被重置或连接关闭时返回false
+    fn write_data(&self, id: u32, mut data: &[u8], end: bool) -> Result<bool, Error> {
+        loop {
+            let n = {
+                let mut state = self.state();
+                loop {
+                    if state.closed {
+                        return Ok(false);
+                    }
+                    let window = match state.streams.get(&id) {
+                        Some(stream) if !stream.reset => stream.window.min(state.window),
+                        _ => return Ok(false),
+                    };
+                    if data.is_empty() || window > 0 {
+                        let n = data.len().min(window as usize).min(state.max_frame_size);
+                        state.window -= n as i64;
+                        state.streams.get_mut(&id).unwrap().window -= n as i64;
+                        break n;
+                    }
+                    state = self.changed.wait(state).unwrap();
+                }
+            };
+            let (chunk, rest) = data.split_at(n);
+            data = rest;
+            let last = data.is_empty();
+            let flags = if end && last { END_STREAM } else { 0 };
+            self.write_frame(DATA, flags, id, chunk)?;
+            if last {
+                return Ok(true);
+            }
+        }
+    }
+
+    fn window_update(&self, id: u32, increment: usize) -> Result<(), Error> {
+        if increment == 0 {
+            return Ok(());
+        }
+        self.write_frame(WINDOW_UPDATE, 0, id, &(increment as u32).to_be_bytes())
+    }
+
+    fn reset_stream(&self, id: u32, code: ErrorCode) -> Result<(), Error> {
+        if let Some(stream) = self.state().streams.get_mut(&id) {
+            stream.reset = true;
+        }
+        self.changed.notify_all();
+        self.write_frame(RST_STREAM, 0, id, &(code as u32).to_be_bytes())
+    }
+
+    fn go_away(&self, code: ErrorCode) -> Result<(), Error> {
+        let last_stream_id = {
+            let mut state = self.state();
+            state.goaway_sent = true;
+            state.last_stream_id
+        };
+        let mut payload = last_stream_id.to_be_bytes().to_vec();
+        payload.extend((code as u32).to_be_bytes());
+        self.write_frame(GOAWAY, 0, 0, &payload)
+    }
+
+    fn write_frame(&self, kind: u8, flags: u8, id: u32, payload: &[u8]) -> Result<(), Error> {
+        let _writer = self.writer.lock().unwrap();
+        Self::write_raw(self.stream, kind, flags, id, payload)
+    }
+
+    fn write_raw(
+        stream: &Stream,
+        kind: u8,
+        flags: u8,
+        id: u32,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.extend([kind, flags]);
+        frame.extend(id.to_be_bytes());
+        frame.extend(payload);
+        let mut w = stream;
+        w.write_all(&frame)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+// 帧内的读超时不能再被当作空闲超时处理
+fn incomplete_frame(e: io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+            io::Error::other(format!("incomplete frame: {}", e)).into()
+        }
+        _ => e.into(),
+    }
+}
+
+// 响应发送完成或出错后关闭流
+struct StreamGuard<'c, 'a>(&'c Connection<'a>, u32);
+
+impl Drop for StreamGuard<'_, '_> {
+    fn drop(&mut self) {
+        self.0.close_stream(self.1);
+    }
+}
+
+/// 请求是否要求升级到h2c，返回解码后的`HTTP2-Settings`(RFC 7540, 3.2)
+pub(crate) fn h2c_upgrade(request: &HttpRequest) -> Option<Vec<u8>> {
+    let has_token = |key: &str, token: &str| {
+        request.get_header(key).is_some_and(|v| {
+            v.split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    if !has_token("Upgrade", "h2c")
+        || !has_token("Connection", "Upgrade")
+        || !has_token("Connection", "HTTP2-Settings")
+    {
+        return None;
+    }
+    URL_SAFE_NO_PAD
+        .decode(request.get_header("HTTP2-Settings")?.trim())
+        .ok()
+}
+
+#[cfg(test)]
+pub(crate) mod test_h2 {
+    use std::{
+        collections::HashMap,
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+        sync::{mpsc, Arc, Barrier, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{HttpServer, Router, ServerHandle};
+
+    pub(crate) type Response = (HashMap<String, String>, Vec<u8>);
+
+    /// 测试用的HTTP/2客户端
+    pub(crate) struct Client<S> {
+        stream: S,
+        encoder: Encoder,
+        decoder: Decoder,
+    }
+
+    impl<S: Read + Write> Client<S> {
+        /// 发送连接序言和SETTINGS
+        pub(crate) fn new(stream: S, settings: &[(u16, u32)]) -> Self {
+            let mut client = Client {
+                stream,
+                encoder: Encoder::new(),
+                decoder: Decoder::new(DEFAULT_TABLE_SIZE),
+            };
+            client.stream.write_all(PREFACE).unwrap();
+            let payload: Vec<u8> = settings
+                .iter()
+                .flat_map(|(id, value)| [&id.to_be_bytes()[..], &value.to_be_bytes()].concat())
+                .collect();
+            client.write_frame(SETTINGS, 0, 0, &payload);
+            client
+        }
+
+        pub(crate) fn write_frame(&mut self, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+            let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+            frame.extend([kind, flags]);
+            frame.extend(id.to_be_bytes());
+            frame.extend(payload);
+            self.stream.write_all(&frame).unwrap();
+        }
+
+        pub(crate) fn request(&mut self, id: u32, method: &str, path: &str, body: Option<&[u8]>) {
+            let block = self.encoder.encode([
+                (":method", method),
+                (":scheme", "http"),
+                (":path", path),
+                (":authority", "localhost"),
+            ]);
+            let flags = END_HEADERS | if body.is_none() { END_STREAM } else { 0 };
+            self.write_frame(HEADERS, flags, id, &block);
+            if let Some(body) = body {
+                self.write_frame(DATA, END_STREAM, id, body);
+            }
+        }
+
+        fn read_frame(&mut self) -> Frame {
+            let mut head = [0; 9];
+            self.stream.read_exact(&mut head).unwrap();
+            let len = u32::from_be_bytes([0, head[0], head[1], head[2]]) as usize;
+            let mut payload = vec![0; len];
+            self.stream.read_exact(&mut payload).unwrap();
+            Frame {
+                kind: head[3],
+                flags: head[4],
+                stream_id: u32::from_be_bytes(head[5..].try_into().unwrap()),
+                payload,
+            }
+        }
+
+        // 跳过连接级别的帧，SETTINGS自动确认
+        fn next_stream_frame(&mut self) -> Frame {
+            loop {
+                let frame = self.read_frame();
+                match frame.kind {
+                    SETTINGS if frame.flags & ACK == 0 => self.write_frame(SETTINGS, ACK, 0, &[]),
+                    SETTINGS | WINDOW_UPDATE | PING => {}
+                    _ => return frame,
+                }
+            }
+        }
+
+        /// 读取指定流的完整响应，其他流的帧按流保存
+        pub(crate) fn responses(&mut self, ids: &[u32]) -> HashMap<u32, Response> {
+            let mut responses: HashMap<u32, Response> = HashMap::new();
+            let mut done = 0;
+            while done < ids.len() {
+                let frame = self.next_stream_frame();
+                assert!(ids.contains(&frame.stream_id), "unexpected {:?}", frame);
+                let response = responses.entry(frame.stream_id).or_default();
+                match frame.kind {
+                    HEADERS => {
+                        let mut block = frame.payload.clone();
+                        let mut flags = frame.flags;
+                        while flags & END_HEADERS == 0 {
+                            let next = self.read_frame();
+                            assert_eq!(next.kind, CONTINUATION);
+                            block.extend(next.payload);
+                            flags = next.flags;
+                        }
+                        response
+                            .0
+                            .extend(self.decoder.decode(&block, usize::MAX).unwrap());
+                    }
+                    DATA => response.1.extend(&frame.payload),
+                    _ => panic!("unexpected {:?}", frame),
+                }
+                if frame.flags & END_STREAM != 0 {
+                    done += 1;
+                }
+            }
+            responses
+        }
+
+        pub(crate) fn response(&mut self, id: u32) -> Response {
+            self.responses(&[id]).remove(&id).unwrap()
+        }
+    }
+
+    fn server(router: Router) -> ServerHandle {
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .configure(HttpServer::set_max_body_size(16))
+            .configure(HttpServer::set_h2c(true))
+            .mount_header("X-Server", "httpx")
+            .mount_route(router);
+        server.spawn().unwrap()
+    }
+
+    fn connect(addr: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    #[test]
+    fn test_prior_knowledge() {
+        let mut router = Router::new();
+        router.get("/", |r, w| {
+            w.write_str(&format!(
+                "{:?} {}",
+                r.version,
+                r.get_header("Host").unwrap()
+            ));
+        });
+        router.post("/echo", |r, w| {
+            w.write_bytes(r.get_body_bytes().unwrap_or_default());
+        });
+        router.get("/chunks", |_r, w| {
+            w.write_chunks((0..3).map(|i| i.to_string().into_bytes()));
+        });
+        router.get("/empty", |_r, w| {
+            w.set_http_state_code(HttpStateCode::StatusNoContent);
+        });
+        let handle = server(router);
+        let addr = handle.local_addr().unwrap();
+        let mut client = Client::new(connect(addr), &[]);
+
+        client.request(1, "GET", "/", None);
+        let (headers, body) = client.response(1);
+        assert_eq!(headers[":status"], "200");
+        assert_eq!(headers["x-server"], "httpx");
+        assert_eq!(headers["content-length"], "14");
+        assert_eq!(body, b"V2_0 localhost");
+
+        client.request(3, "POST", "/echo", Some(b"hello"));
+        client.request(5, "GET", "/chunks", None);
+        client.request(7, "HEAD", "/", None);
+        client.request(9, "GET", "/missing", None);
+        let responses = client.responses(&[3, 5, 7, 9]);
+        assert_eq!(responses[&3].1, b"hello");
+        assert_eq!(responses[&5].1, b"012");
+        assert!(!responses[&5].0.contains_key("content-length"));
+        assert_eq!(responses[&7].0["content-length"], "14");
+        assert!(responses[&7].1.is_empty());
+        assert_eq!(responses[&9].0[":status"], "404");
+
+        // 请求体超出限制
+        client.request(11, "POST", "/echo", Some(&[b'x'; 17]));
+        assert_eq!(client.response(11).0[":status"], "413");
+
+        // 204不携带content-length
+        let mut client = Client::new(connect(addr), &[]);
+        client.request(1, "GET", "/empty", None);
+        let (headers, body) = client.response(1);
+        assert_eq!(headers[":status"], "204");
+        assert!(!headers.contains_key("content-length"));
+        assert!(body.is_empty());
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_multiplexing() {
+        // 两个请求都到达屏障后才能返回，只有并发处理时才能完成
+        let barrier = Arc::new(Barrier::new(2));
+        let mut router = Router::new();
+        let b = barrier.clone();
+        router.get("/a", move |_r, w| {
+            b.wait();
+            w.write_str("a");
+        });
+        router.get("/b", move |_r, w| {
+            barrier.wait();
+            w.write_str("b");
+        });
+        let handle = server(router);
+        let mut client = Client::new(connect(handle.local_addr().unwrap()), &[]);
+        client.request(1, "GET", "/a", None);
+        client.request(3, "GET", "/b", None);
+        let responses = client.responses(&[1, 3]);
+        assert_eq!(responses[&1].1, b"a");
+        assert_eq!(responses[&3].1, b"b");
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_stream_limit() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let rx = Mutex::new(rx);
+        let mut router = Router::new();
+        router.get("/wait", move |_r, w| {
+            rx.lock().unwrap().recv().unwrap();
+            w.write_str("done");
+        });
+        router.get("/", |_r, w| {
+            w.write_str("ok");
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .configure(HttpServer::set_h2c(true))
+            .configure(HttpServer::set_max_http2_streams(2))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        // 上限在所有连接之间共享，超出的流被拒绝，不影响已经在处理的请求
+        let mut first = Client::new(connect(addr), &[]);
+        first.request(1, "GET", "/wait", None);
+        first.request(3, "GET", "/wait", None);
+        let mut second = Client::new(connect(addr), &[]);
+        second.request(1, "GET", "/wait", None);
+        let frame = second.next_stream_frame();
+        assert_eq!((frame.kind, frame.stream_id), (RST_STREAM, 1));
+        assert_eq!(frame.u32_at(0), ErrorCode::RefusedStream as u32);
+
+        tx.send(()).unwrap();
+        tx.send(()).unwrap();
+        let responses = first.responses(&[1, 3]);
+        assert_eq!(responses[&1].1, b"done");
+        assert_eq!(responses[&3].1, b"done");
+
+        // 流在响应发送完成后才关闭并归还，被拒绝时稍后重试
+        let mut id = 3;
+        loop {
+            second.request(id, "GET", "/", None);
+            let frame = second.next_stream_frame();
+            if frame.kind == HEADERS {
+                break;
+            }
+            assert!(id < 99, "stream limit not released");
+            thread::sleep(Duration::from_millis(10));
+            id += 2;
+        }
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut router = Router::new();
+        router.get("/", |_r, w| {
+            w.write_str(&"x".repeat(30));
+        });
+        let handle = server(router);
+        let mut client = Client::new(
+            connect(handle.local_addr().unwrap()),
+            &[(SETTINGS_INITIAL_WINDOW_SIZE, 10)],
+        );
+        client.request(1, "GET", "/", None);
+
+        assert_eq!(client.next_stream_frame().kind, HEADERS);
+        let frame = client.next_stream_frame();
+        assert_eq!((frame.kind, frame.payload.len()), (DATA, 10));
+        client.write_frame(WINDOW_UPDATE, 0, 1, &20u32.to_be_bytes());
+        let frame = client.next_stream_frame();
+        assert_eq!((frame.kind, frame.flags), (DATA, END_STREAM));
+        assert_eq!(frame.payload.len(), 20);
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_h2c_upgrade() {
+        let mut router = Router::new();
+        router.post("/", |r, w| {
+            w.write_str(&format!("upgraded {}", r.get_body().unwrap()));
+        });
+        let handle = server(router);
+        let mut stream = connect(handle.local_addr().unwrap());
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\
+                  Connection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\n\
+                  HTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\nbody",
+            )
+            .unwrap();
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut b = [0];
+            stream.read_exact(&mut b).unwrap();
+            head.push(b[0]);
+        }
+        assert!(head.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+
+        let mut client = Client::new(stream, &[]);
+        let (headers, body) = client.response(1);
+        assert_eq!(headers[":status"], "200");
+        assert_eq!(body, b"upgraded body");
+        handle.shutdown().unwrap();
+
+        // 默认不启用h2c，按HTTP/1.1处理
+        let mut router = Router::new();
+        router.get("/", |r, w| {
+            w.write_str(&format!("{:?}", r.version));
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+        let mut stream = connect(handle.local_addr().unwrap());
+        stream
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings, close\r\n\
+                  Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n",
+            )
+            .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.ends_with("V1_1"));
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        let mut router = Router::new();
+        router.get("/panic", |_r, _w| {
+            panic!("handler panic");
+        });
+        router.get("/body", |_r, w| {
+            w.write_chunks((0..2).map(|i| match i {
+                0 => b"a".to_vec(),
+                _ => panic!("body panic"),
+            }));
+        });
+        router.get("/", |_r, w| {
+            w.write_str("ok");
+        });
+        let handle = server(router);
+        let mut client = Client::new(connect(handle.local_addr().unwrap()), &[]);
+
+        client.request(1, "GET", "/panic", None);
+        let (headers, _) = client.response(1);
+        assert_eq!(headers[":status"], "500");
+        assert_eq!(headers["x-server"], "httpx");
+
+        // 响应头已发出，只能重置流
+        client.request(3, "GET", "/body", None);
+        let mut frame = client.next_stream_frame();
+        while frame.kind != RST_STREAM {
+            frame = client.next_stream_frame();
+        }
+        assert_eq!(frame.stream_id, 3);
+        assert_eq!(frame.u32_at(0), ErrorCode::InternalError as u32);
+
+        // 连接上的其他流不受影响
+        client.request(5, "GET", "/", None);
+        assert_eq!(client.response(5).1, b"ok");
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_header_list_limit() {
+        let mut router = Router::new();
+        router.get("/", |_r, w| {
+            w.write_str("ok");
+        });
+        let handle = server(router);
+        let mut client = Client::new(connect(handle.local_addr().unwrap()), &[]);
+
+        // 插入4000字节的动态表条目后重复引用，不到16KB的头部块解码后约为40MB
+        let mut block =
+            client
+                .encoder
+                .encode([(":method", "GET"), (":scheme", "http"), (":path", "/")]);
+        block.extend([0x40, 0x01, b'x', 0x7f, 0xa1, 0x1e]);
+        block.extend([b'v'; 4000]);
+        block.extend([0xbe; 10000]);
+        client.write_frame(HEADERS, END_HEADERS | END_STREAM, 1, &block);
+        assert_eq!(client.response(1).0[":status"], "431");
+
+        // 动态表仍然同步，连接可以继续使用
+        client.request(3, "GET", "/", None);
+        assert_eq!(client.response(3).1, b"ok");
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_read_frame_timeout() {
+        // 按顺序返回给定的数据或错误
+        struct Script(Vec<Option<&'static [u8]>>);
+
+        impl Read for Script {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0.is_empty() {
+                    return Ok(0);
+                }
+                match self.0.remove(0) {
+                    Some(data) => {
+                        buf[..data.len()].copy_from_slice(data);
+                        Ok(data.len())
+                    }
+                    None => Err(ErrorKind::WouldBlock.into()),
+                }
+            }
+        }
+
+        let head: &[u8] = &[0, 0, 1, PING, 0, 0, 0, 0, 0];
+        let timed_out = |result: Result<Option<Frame>, Error>| match result {
+            Err(Error::Io(e)) => e.kind() == ErrorKind::WouldBlock,
+            _ => false,
+        };
+
+        // 帧之间的超时可以继续读取
+        let mut reader = BufReader::new(Script(vec![None, Some(head), Some(b"x")]));
+        assert!(timed_out(Connection::read_frame(&mut reader)));
+        let frame = Connection::read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!((frame.kind, frame.payload), (PING, b"x".to_vec()));
+        assert!(Connection::read_frame(&mut reader).unwrap().is_none());
+
+        // 帧头或负载读到一半超时
+        let scripts = [
+            vec![Some(&head[..4]), None, Some(&head[4..]), Some(b"x")],
+            vec![Some(head), None, Some(b"x")],
+        ];
+        for script in scripts {
+            let mut reader = BufReader::new(Script(script));
+            let result = Connection::read_frame(&mut reader);
+            assert!(matches!(result, Err(Error::Io(_))) && !timed_out(result));
+        }
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let handle = server(Router::new());
+        let addr = handle.local_addr().unwrap();
+
+        // 大写的请求头名称属于格式错误的请求，只重置该流
+        let mut client = Client::new(connect(addr), &[]);
+        let block = client.encoder.encode([
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            ("X-Upper", "1"),
+        ]);
+        client.write_frame(HEADERS, END_HEADERS | END_STREAM, 1, &block);
+        let frame = client.next_stream_frame();
+        assert_eq!((frame.kind, frame.stream_id), (RST_STREAM, 1));
+        assert_eq!(frame.u32_at(0), ErrorCode::ProtocolError as u32);
+        client.request(3, "GET", "/", None);
+        assert_eq!(client.response(3).0[":status"], "404");
+
+        // 流0上的DATA属于连接错误
+        client.write_frame(DATA, 0, 0, b"x");
+        let frame = client.next_stream_frame();
+        assert_eq!(frame.kind, GOAWAY);
+        assert_eq!(frame.u32_at(0), 3);
+        assert_eq!(frame.u32_at(4), ErrorCode::ProtocolError as u32);
+        handle.shutdown().unwrap();
+    }
+}
@@ -1,3 +1,13 @@
-use super::{request, response};
+use std::sync::Arc;
 
-pub type Handler = fn(request: &request::HttpRequest, response: &mut response::HttpResponse);
\ No newline at end of file
+use super::{request, response, websocket};
+
+/// 路由处理函数，可以是普通函数，也可以是捕获了`Arc`等共享状态的闭包
+///
+/// 使用`Arc`保存，同一个处理函数可以被多个路由共享
+pub type Handler =
+    Arc<dyn Fn(&request::HttpRequest, &mut response::HttpResponse) + Send + Sync + 'static>;
+
+/// WebSocket处理函数，握手成功后在独立的线程中调用，返回时关闭连接
+pub type WebSocketHandler =
+    Arc<dyn Fn(&request::HttpRequest, websocket::WebSocket) + Send + Sync + 'static>;
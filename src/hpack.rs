@@ -0,0 +1,864 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    error::Error,
+    fmt::{Display, Formatter},
+    sync::OnceLock,
+};
+
+/// 动态表的默认最大长度，即SETTINGS_HEADER_TABLE_SIZE的初始值
+pub(crate) const DEFAULT_TABLE_SIZE: usize = 4096;
+
+// 每个条目在动态表中额外计入的长度(RFC 7541, 4.1)
+const ENTRY_OVERHEAD: usize = 32;
+
+/// 头部块无法解码，对应HTTP/2的COMPRESSION_ERROR，出现后整个连接不可再用
+#[derive(Debug, PartialEq)]
+pub(crate) struct HpackError(&'static str);
+
+impl Display for HpackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "hpack decode failed: {}", self.0)
+    }
+}
+
+impl Error for HpackError {}
+
+/// 解码后的头部列表超出上限，此时整个头部块已经解码完成，动态表与对端仍保持一致
+pub(crate) const HEADER_LIST_TOO_LARGE: HpackError = HpackError("header list too large");
+
+// 静态表(RFC 7541, 附录A)，索引从1开始
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// 动态表，新条目插入在最前面，按字节计算长度以便与对端保持一致
+#[derive(Debug)]
+struct DynamicTable {
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> Self {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) {
+        let size = name.len() + value.len() + ENTRY_OVERHEAD;
+        self.size += size;
+        self.entries.push_front((name, value));
+        self.evict();
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict();
+    }
+
+    // 超出最大长度时从最旧的条目开始移除，比最大长度还大的条目会清空整个表
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break,
+            }
+        }
+    }
+}
+
+/// HPACK解码器，每个连接一个，按头部块的接收顺序调用
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    table: DynamicTable,
+    // 通过SETTINGS_HEADER_TABLE_SIZE告知对端的上限
+    max_table_size: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new(max_table_size: usize) -> Self {
+        Decoder {
+            table: DynamicTable::new(max_table_size),
+            max_table_size,
+        }
+    }
+
+    /// 解码一个完整的头部块，非UTF-8的字段按有损方式转换
+    ///
+    /// 解码时按RFC 9113, 6.5.2累计头部列表长度，超出max_list_size后不再保留字段，
+    /// 避免少量重复的索引展开成大量数据，解码完成后返回`HEADER_LIST_TOO_LARGE`
+    pub(crate) fn decode(
+        &mut self,
+        mut block: &[u8],
+        max_list_size: usize,
+    ) -> Result<Vec<(String, String)>, HpackError> {
+        let mut headers = Vec::new();
+        let mut size = 0;
+        while let Some(&first) = block.first() {
+            let (name, value): (Cow<[u8]>, Cow<[u8]>) = if first & 0x80 != 0 {
+                let index = decode_int(&mut block, 7)?;
+                let (name, value) = self.get(index)?;
+                (name.into(), value.into())
+            } else if first & 0x40 != 0 {
+                let (name, value) = self.decode_literal(&mut block, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                (name.into(), value.into())
+            } else if first & 0x20 != 0 {
+                // 表长度更新只能出现在头部块的开始位置
+                if size > 0 {
+                    return Err(HpackError("table size update after header field"));
+                }
+                let size = decode_int(&mut block, 5)?;
+                if size > self.max_table_size {
+                    return Err(HpackError("table size update exceeds limit"));
+                }
+                self.table.set_max_size(size);
+                continue;
+            } else {
+                // 不索引(0000)与永不索引(0001)的字面量，解码方式相同
+                let (name, value) = self.decode_literal(&mut block, 4)?;
+                (name.into(), value.into())
+            };
+            size += name.len() + value.len() + ENTRY_OVERHEAD;
+            if size <= max_list_size {
+                headers.push((
+                    String::from_utf8_lossy(&name).to_string(),
+                    String::from_utf8_lossy(&value).to_string(),
+                ));
+            }
+        }
+        match size > max_list_size {
+            true => Err(HEADER_LIST_TOO_LARGE),
+            false => Ok(headers),
+        }
+    }
+
+    fn get(&self, index: usize) -> Result<(&[u8], &[u8]), HpackError> {
+        match index {
+            0 => Err(HpackError("index 0 is not used")),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.as_bytes(), value.as_bytes()))
+            }
+            _ => self
+                .table
+                .entries
+                .get(index - STATIC_TABLE.len() - 1)
+                .map(|(name, value)| (name.as_slice(), value.as_slice()))
+                .ok_or(HpackError("index out of range")),
+        }
+    }
+
+    fn decode_literal(
+        &self,
+        block: &mut &[u8],
+        prefix: u8,
+    ) -> Result<(Vec<u8>, Vec<u8>), HpackError> {
+        let name = match decode_int(block, prefix)? {
+            0 => decode_string(block)?,
+            index => self.get(index)?.0.to_vec(),
+        };
+        let value = decode_string(block)?;
+        Ok((name, value))
+    }
+}
+
+/// HPACK编码器，不使用动态表，响应头以字面量或静态表索引发送
+#[derive(Debug)]
+pub(crate) struct Encoder {
+    table_size: usize,
+    // 对端缩小了动态表上限，下一个头部块开始时需要声明
+    size_update: Option<usize>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        Encoder {
+            table_size: DEFAULT_TABLE_SIZE,
+            size_update: None,
+        }
+    }
+
+    /// 对端通过SETTINGS_HEADER_TABLE_SIZE修改了动态表上限
+    pub(crate) fn set_max_table_size(&mut self, size: usize) {
+        if size < self.table_size {
+            self.table_size = size;
+            self.size_update = Some(size);
+        }
+    }
+
+    /// 编码一组头部字段，名称应为小写
+    pub(crate) fn encode<'a, I>(&mut self, headers: I) -> Vec<u8>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut block = Vec::new();
+        if let Some(size) = self.size_update.take() {
+            encode_int(&mut block, size, 5, 0x20);
+        }
+        for (name, value) in headers {
+            if let Some(i) = STATIC_TABLE.iter().position(|&e| e == (name, value)) {
+                encode_int(&mut block, i + 1, 7, 0x80);
+                continue;
+            }
+            let name_index = STATIC_TABLE
+                .iter()
+                .position(|&(n, _)| n == name)
+                .map_or(0, |i| i + 1);
+            encode_int(&mut block, name_index, 4, 0x00);
+            if name_index == 0 {
+                encode_string(&mut block, name.as_bytes());
+            }
+            encode_string(&mut block, value.as_bytes());
+        }
+        block
+    }
+}
+
+// 整数编码(RFC 7541, 5.1)，flags为第一个字节中前缀之外的高位
+fn encode_int(out: &mut Vec<u8>, mut value: usize, prefix: u8, flags: u8) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    value -= max;
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn decode_int(block: &mut &[u8], prefix: u8) -> Result<usize, HpackError> {
+    let max = (1usize << prefix) - 1;
+    let (&first, rest) = block.split_first().ok_or(HpackError("truncated integer"))?;
+    *block = rest;
+    let mut value = first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&b, rest) = block.split_first().ok_or(HpackError("truncated integer"))?;
+        *block = rest;
+        // 超过4个续字节的整数不会出现在合理的头部块中
+        if shift > 21 {
+            return Err(HpackError("integer overflow"));
+        }
+        value += ((b & 0x7f) as usize) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+// Huffman编码更短时使用Huffman编码
+fn encode_string(out: &mut Vec<u8>, data: &[u8]) {
+    let len = huffman_len(data);
+    if len < data.len() {
+        encode_int(out, len, 7, 0x80);
+        huffman_encode(data, out);
+    } else {
+        encode_int(out, data.len(), 7, 0x00);
+        out.extend_from_slice(data);
+    }
+}
+
+fn decode_string(block: &mut &[u8]) -> Result<Vec<u8>, HpackError> {
+    let huffman = block.first().is_some_and(|b| b & 0x80 != 0);
+    let len = decode_int(block, 7)?;
+    if len > block.len() {
+        return Err(HpackError("truncated string"));
+    }
+    let (data, rest) = block.split_at(len);
+    *block = rest;
+    match huffman {
+        true => huffman_decode(data),
+        false => Ok(data.to_vec()),
+    }
+}
+
+fn huffman_len(data: &[u8]) -> usize {
+    let bits: usize = data
+        .iter()
+        .map(|&b| HUFFMAN_CODES[b as usize].1 as usize)
+        .sum();
+    bits.div_ceil(8)
+}
+
+fn huffman_encode(data: &[u8], out: &mut Vec<u8>) {
+    let mut bits: u64 = 0;
+    let mut len = 0;
+    for &b in data {
+        let (code, code_len) = HUFFMAN_CODES[b as usize];
+        bits = (bits << code_len) | code as u64;
+        len += code_len;
+        while len >= 8 {
+            len -= 8;
+            out.push((bits >> len) as u8);
+        }
+    }
+    // 不足一个字节的部分以EOS的高位(全1)填充
+    if len > 0 {
+        out.push(((bits << (8 - len)) as u8) | (0xff >> len));
+    }
+}
+
+// 码表是规范Huffman编码(同一长度的编码按符号顺序连续分配)，
+// 按长度记录第一个编码即可逐位解码
+struct HuffmanDecoder {
+    // 下标为编码长度
+    first_code: [u32; 31],
+    count: [u32; 31],
+    offset: [usize; 31],
+    symbols: Vec<u16>,
+}
+
+fn huffman_decoder() -> &'static HuffmanDecoder {
+    static DECODER: OnceLock<HuffmanDecoder> = OnceLock::new();
+    DECODER.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..HUFFMAN_CODES.len() as u16).collect();
+        symbols.sort_by_key(|&s| (HUFFMAN_CODES[s as usize].1, HUFFMAN_CODES[s as usize].0));
+        let mut decoder = HuffmanDecoder {
+            first_code: [0; 31],
+            count: [0; 31],
+            offset: [0; 31],
+            symbols,
+        };
+        for (i, &s) in decoder.symbols.iter().enumerate() {
+            let (code, len) = HUFFMAN_CODES[s as usize];
+            let len = len as usize;
+            if decoder.count[len] == 0 {
+                decoder.first_code[len] = code;
+                decoder.offset[len] = i;
+            }
+            decoder.count[len] += 1;
+        }
+        decoder
+    })
+}
+
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let decoder = huffman_decoder();
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let mut code: u32 = 0;
+    let mut len = 0;
+    for &b in data {
+        for i in (0..8).rev() {
+            code = (code << 1) | ((b >> i) & 1) as u32;
+            len += 1;
+            if len > 30 {
+                return Err(HpackError("invalid huffman code"));
+            }
+            let n = code.wrapping_sub(decoder.first_code[len]);
+            if code >= decoder.first_code[len] && n < decoder.count[len] {
+                match decoder.symbols[decoder.offset[len] + n as usize] {
+                    256 => return Err(HpackError("huffman string contains EOS")),
+                    symbol => out.push(symbol as u8),
+                }
+                code = 0;
+                len = 0;
+            }
+        }
+    }
+    // 填充最多7位，且必须是EOS的高位
+    if len > 7 || code != (1 << len) - 1 {
+        return Err(HpackError("invalid huffman padding"));
+    }
+    Ok(out)
+}
+
+// Huffman码表(RFC 7541, 附录B)，(编码, 位数)，下标为符号，256为EOS
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13),     // 0
+    (0x7fffd8, 23),   // 1
+    (0xfffffe2, 28),  // 2
+    (0xfffffe3, 28),  // 3
+    (0xfffffe4, 28),  // 4
+    (0xfffffe5, 28),  // 5
+    (0xfffffe6, 28),  // 6
+    (0xfffffe7, 28),  // 7
+    (0xfffffe8, 28),  // 8
+    (0xffffea, 24),   // 9
+    (0x3ffffffc, 30), // 10
+    (0xfffffe9, 28),  // 11
+    (0xfffffea, 28),  // 12
+    (0x3ffffffd, 30), // 13
+    (0xfffffeb, 28),  // 14
+    (0xfffffec, 28),  // 15
+    (0xfffffed, 28),  // 16
+    (0xfffffee, 28),  // 17
+    (0xfffffef, 28),  // 18
+    (0xffffff0, 28),  // 19
+    (0xffffff1, 28),  // 20
+    (0xffffff2, 28),  // 21
+    (0x3ffffffe, 30), // 22
+    (0xffffff3, 28),  // 23
+    (0xffffff4, 28),  // 24
+    (0xffffff5, 28),  // 25
+    (0xffffff6, 28),  // 26
+    (0xffffff7, 28),  // 27
+    (0xffffff8, 28),  // 28
+    (0xffffff9, 28),  // 29
+    (0xffffffa, 28),  // 30
+    (0xffffffb, 28),  // 31
+    (0x14, 6),        // 32
+    (0x3f8, 10),      // '!'
+    (0x3f9, 10),      // '"'
+    (0xffa, 12),      // '#'
+    (0x1ff9, 13),     // '$'
+    (0x15, 6),        // '%'
+    (0xf8, 8),        // '&'
+    (0x7fa, 11),      // "'"
+    (0x3fa, 10),      // '('
+    (0x3fb, 10),      // ')'
+    (0xf9, 8),        // '*'
+    (0x7fb, 11),      // '+'
+    (0xfa, 8),        // ','
+    (0x16, 6),        // '-'
+    (0x17, 6),        // '.'
+    (0x18, 6),        // '/'
+    (0x0, 5),         // '0'
+    (0x1, 5),         // '1'
+    (0x2, 5),         // '2'
+    (0x19, 6),        // '3'
+    (0x1a, 6),        // '4'
+    (0x1b, 6),        // '5'
+    (0x1c, 6),        // '6'
+    (0x1d, 6),        // '7'
+    (0x1e, 6),        // '8'
+    (0x1f, 6),        // '9'
+    (0x5c, 7),        // ':'
+    (0xfb, 8),        // ';'
+    (0x7ffc, 15),     // '<'
+    (0x20, 6),        // '='
+    (0xffb, 12),      // '>'
+    (0x3fc, 10),      // '?'
+    (0x1ffa, 13),     // '@'
+    (0x21, 6),        // 'A'
+    (0x5d, 7),        // 'B'
+    (0x5e, 7),        // 'C'
+    (0x5f, 7),        // 'D'
+    (0x60, 7),        // 'E'
+    (0x61, 7),        // 'F'
+    (0x62, 7),        // 'G'
+    (0x63, 7),        // 'H'
+    (0x64, 7),        // 'I'
+    (0x65, 7),        // 'J'
+    (0x66, 7),        // 'K'
+    (0x67, 7),        // 'L'
+    (0x68, 7),        // 'M'
+    (0x69, 7),        // 'N'
+    (0x6a, 7),        // 'O'
+    (0x6b, 7),        // 'P'
+    (0x6c, 7),        // 'Q'
+    (0x6d, 7),        // 'R'
+    (0x6e, 7),        // 'S'
+    (0x6f, 7),        // 'T'
+    (0x70, 7),        // 'U'
+    (0x71, 7),        // 'V'
+    (0x72, 7),        // 'W'
+    (0xfc, 8),        // 'X'
+    (0x73, 7),        // 'Y'
+    (0xfd, 8),        // 'Z'
+    (0x1ffb, 13),     // '['
+    (0x7fff0, 19),    // '\\'
+    (0x1ffc, 13),     // ']'
+    (0x3ffc, 14),     // '^'
+    (0x22, 6),        // '_'
+    (0x7ffd, 15),     // '`'
+    (0x3, 5),         // 'a'
+    (0x23, 6),        // 'b'
+    (0x4, 5),         // 'c'
+    (0x24, 6),        // 'd'
+    (0x5, 5),         // 'e'
+    (0x25, 6),        // 'f'
+    (0x26, 6),        // 'g'
+    (0x27, 6),        // 'h'
+    (0x6, 5),         // 'i'
+    (0x74, 7),        // 'j'
+    (0x75, 7),        // 'k'
+    (0x28, 6),        // 'l'
+    (0x29, 6),        // 'm'
+    (0x2a, 6),        // 'n'
+    (0x7, 5),         // 'o'
+    (0x2b, 6),        // 'p'
+    (0x76, 7),        // 'q'
+    (0x2c, 6),        // 'r'
+    (0x8, 5),         // 's'
+    (0x9, 5),         // 't'
+    (0x2d, 6),        // 'u'
+    (0x77, 7),        // 'v'
+    (0x78, 7),        // 'w'
+    (0x79, 7),        // 'x'
+    (0x7a, 7),        // 'y'
+    (0x7b, 7),        // 'z'
+    (0x7ffe, 15),     // '{'
+    (0x7fc, 11),      // '|'
+    (0x3ffd, 14),     // '}'
+    (0x1ffd, 13),     // '~'
+    (0xffffffc, 28),  // 127
+    (0xfffe6, 20),    // 128
+    (0x3fffd2, 22),   // 129
+    (0xfffe7, 20),    // 130
+    (0xfffe8, 20),    // 131
+    (0x3fffd3, 22),   // 132
+    (0x3fffd4, 22),   // 133
+    (0x3fffd5, 22),   // 134
+    (0x7fffd9, 23),   // 135
+    (0x3fffd6, 22),   // 136
+    (0x7fffda, 23),   // 137
+    (0x7fffdb, 23),   // 138
+    (0x7fffdc, 23),   // 139
+    (0x7fffdd, 23),   // 140
+    (0x7fffde, 23),   // 141
+    (0xffffeb, 24),   // 142
+    (0x7fffdf, 23),   // 143
+    (0xffffec, 24),   // 144
+    (0xffffed, 24),   // 145
+    (0x3fffd7, 22),   // 146
+    (0x7fffe0, 23),   // 147
+    (0xffffee, 24),   // 148
+    (0x7fffe1, 23),   // 149
+    (0x7fffe2, 23),   // 150
+    (0x7fffe3, 23),   // 151
+    (0x7fffe4, 23),   // 152
+    (0x1fffdc, 21),   // 153
+    (0x3fffd8, 22),   // 154
+    (0x7fffe5, 23),   // 155
+    (0x3fffd9, 22),   // 156
+    (0x7fffe6, 23),   // 157
+    (0x7fffe7, 23),   // 158
+    (0xffffef, 24),   // 159
+    (0x3fffda, 22),   // 160
+    (0x1fffdd, 21),   // 161
+    (0xfffe9, 20),    // 162
+    (0x3fffdb, 22),   // 163
+    (0x3fffdc, 22),   // 164
+    (0x7fffe8, 23),   // 165
+    (0x7fffe9, 23),   // 166
+    (0x1fffde, 21),   // 167
+    (0x7fffea, 23),   // 168
+    (0x3fffdd, 22),   // 169
+    (0x3fffde, 22),   // 170
+    (0xfffff0, 24),   // 171
+    (0x1fffdf, 21),   // 172
+    (0x3fffdf, 22),   // 173
+    (0x7fffeb, 23),   // 174
+    (0x7fffec, 23),   // 175
+    (0x1fffe0, 21),   // 176
+    (0x1fffe1, 21),   // 177
+    (0x3fffe0, 22),   // 178
+    (0x1fffe2, 21),   // 179
+    (0x7fffed, 23),   // 180
+    (0x3fffe1, 22),   // 181
+    (0x7fffee, 23),   // 182
+    (0x7fffef, 23),   // 183
+    (0xfffea, 20),    // 184
+    (0x3fffe2, 22),   // 185
+    (0x3fffe3, 22),   // 186
+    (0x3fffe4, 22),   // 187
+    (0x7ffff0, 23),   // 188
+    (0x3fffe5, 22),   // 189
+    (0x3fffe6, 22),   // 190
+    (0x7ffff1, 23),   // 191
+    (0x3ffffe0, 26),  // 192
+    (0x3ffffe1, 26),  // 193
+    (0xfffeb, 20),    // 194
+    (0x7fff1, 19),    // 195
+    (0x3fffe7, 22),   // 196
+    (0x7ffff2, 23),   // 197
+    (0x3fffe8, 22),   // 198
+    (0x1ffffec, 25),  // 199
+    (0x3ffffe2, 26),  // 200
+    (0x3ffffe3, 26),  // 201
+    (0x3ffffe4, 26),  // 202
+    (0x7ffffde, 27),  // 203
+    (0x7ffffdf, 27),  // 204
+    (0x3ffffe5, 26),  // 205
+    (0xfffff1, 24),   // 206
+    (0x1ffffed, 25),  // 207
+    (0x7fff2, 19),    // 208
+    (0x1fffe3, 21),   // 209
+    (0x3ffffe6, 26),  // 210
+    (0x7ffffe0, 27),  // 211
+    (0x7ffffe1, 27),  // 212
+    (0x3ffffe7, 26),  // 213
+    (0x7ffffe2, 27),  // 214
+    (0xfffff2, 24),   // 215
+    (0x1fffe4, 21),   // 216
+    (0x1fffe5, 21),   // 217
+    (0x3ffffe8, 26),  // 218
+    (0x3ffffe9, 26),  // 219
+    (0xffffffd, 28),  // 220
+    (0x7ffffe3, 27),  // 221
+    (0x7ffffe4, 27),  // 222
+    (0x7ffffe5, 27),  // 223
+    (0xfffec, 20),    // 224
+    (0xfffff3, 24),   // 225
+    (0xfffed, 20),    // 226
+    (0x1fffe6, 21),   // 227
+    (0x3fffe9, 22),   // 228
+    (0x1fffe7, 21),   // 229
+    (0x1fffe8, 21),   // 230
+    (0x7ffff3, 23),   // 231
+    (0x3fffea, 22),   // 232
+    (0x3fffeb, 22),   // 233
+    (0x1ffffee, 25),  // 234
+    (0x1ffffef, 25),  // 235
+    (0xfffff4, 24),   // 236
+    (0xfffff5, 24),   // 237
+    (0x3ffffea, 26),  // 238
+    (0x7ffff4, 23),   // 239
+    (0x3ffffeb, 26),  // 240
+    (0x7ffffe6, 27),  // 241
+    (0x3ffffec, 26),  // 242
+    (0x3ffffed, 26),  // 243
+    (0x7ffffe7, 27),  // 244
+    (0x7ffffe8, 27),  // 245
+    (0x7ffffe9, 27),  // 246
+    (0x7ffffea, 27),  // 247
+    (0x7ffffeb, 27),  // 248
+    (0xffffffe, 28),  // 249
+    (0x7ffffec, 27),  // 250
+    (0x7ffffed, 27),  // 251
+    (0x7ffffee, 27),  // 252
+    (0x7ffffef, 27),  // 253
+    (0x7fffff0, 27),  // 254
+    (0x3ffffee, 26),  // 255
+    (0x3fffffff, 30), // EOS
+];
+
+#[cfg(test)]
+mod test_hpack {
+    use super::{
+        huffman_decode, Decoder, Encoder, HpackError, DEFAULT_TABLE_SIZE, HEADER_LIST_TOO_LARGE,
+    };
+
+    const NO_LIMIT: usize = usize::MAX;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn pairs(headers: &[(String, String)]) -> Vec<(&str, &str)> {
+        headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    // RFC 7541, C.3 与 C.4：同一组请求分别不使用与使用Huffman编码
+    #[test]
+    fn test_decode_requests() {
+        for blocks in [
+            [
+                "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+                "8286 84be 5808 6e6f 2d63 6163 6865",
+                "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+            ],
+            [
+                "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+                "8286 84be 5886 a8eb 1064 9cbf",
+                "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+            ],
+        ] {
+            let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+            let headers = decoder.decode(&hex(blocks[0]), NO_LIMIT).unwrap();
+            assert_eq!(
+                pairs(&headers),
+                [
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ]
+            );
+            assert_eq!(decoder.table.size, 57);
+
+            let headers = decoder.decode(&hex(blocks[1]), NO_LIMIT).unwrap();
+            assert_eq!(pairs(&headers)[4], ("cache-control", "no-cache"));
+            assert_eq!(decoder.table.size, 110);
+
+            let headers = decoder.decode(&hex(blocks[2]), NO_LIMIT).unwrap();
+            assert_eq!(
+                pairs(&headers),
+                [
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ]
+            );
+            assert_eq!(decoder.table.size, 164);
+        }
+    }
+
+    // RFC 7541, C.5：动态表上限为256，第二个响应插入时淘汰最旧的条目
+    #[test]
+    fn test_decode_eviction() {
+        let mut decoder = Decoder::new(256);
+        let headers = decoder
+            .decode(
+                &hex(
+                    "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 \
+                 3133 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 \
+                 7861 6d70 6c65 2e63 6f6d",
+                ),
+                NO_LIMIT,
+            )
+            .unwrap();
+        assert_eq!(pairs(&headers)[0], (":status", "302"));
+        assert_eq!(decoder.table.size, 222);
+
+        let headers = decoder
+            .decode(&hex("4803 3330 37c1 c0bf"), NO_LIMIT)
+            .unwrap();
+        assert_eq!(
+            pairs(&headers),
+            [
+                (":status", "307"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ]
+        );
+        assert_eq!(decoder.table.size, 222);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let long = "x".repeat(300);
+        let headers = [
+            (":status", "200"),
+            (":status", "302"),
+            ("content-type", "text/html; charset=utf-8"),
+            ("x-custom", long.as_str()),
+            ("x-bytes", "\u{7f}~|"),
+        ];
+        let mut encoder = Encoder::new();
+        encoder.set_max_table_size(0);
+        let block = encoder.encode(headers);
+        // 索引表示的:status 200，之后是表长度更新
+        assert_eq!(block[..2], [0x20, 0x88]);
+
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+        assert_eq!(pairs(&decoder.decode(&block, NO_LIMIT).unwrap()), headers);
+        let block = encoder.encode(headers);
+        assert_eq!(block[0], 0x88);
+        assert_eq!(pairs(&decoder.decode(&block, NO_LIMIT).unwrap()), headers);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+        assert_eq!(
+            decoder.decode(&[0x80], NO_LIMIT),
+            Err(HpackError("index 0 is not used"))
+        );
+        assert!(decoder.decode(&[0xbe], NO_LIMIT).is_err());
+        assert!(decoder.decode(&hex("82 3fe1 1f"), NO_LIMIT).is_err());
+        assert!(decoder.decode(&hex("0a 05 6162"), NO_LIMIT).is_err());
+        // 填充位不全为1、填充超过7位
+        assert!(huffman_decode(&[0x00]).is_err());
+        assert!(huffman_decode(&[0x1f, 0xff]).is_err());
+        assert_eq!(huffman_decode(&[0x1f]), Ok(b"a".to_vec()));
+    }
+
+    // 插入一个较大的动态表条目后重复引用，解码后的长度远大于头部块本身
+    #[test]
+    fn test_header_list_limit() {
+        let mut block = vec![0x40, 0x01, b'x', 0x7f];
+        block.extend([0xa1, 0x1e]); // 值长度127 + 33 + 30 * 128 = 4000
+        block.extend([b'v'; 4000]);
+        block.extend([0xbe; 1000]);
+        let mut decoder = Decoder::new(DEFAULT_TABLE_SIZE);
+        assert_eq!(
+            decoder.decode(&block, 16 * 1024),
+            Err(HEADER_LIST_TOO_LARGE)
+        );
+        // 整个头部块仍被处理，动态表与编码方保持一致
+        assert_eq!(decoder.table.entries.len(), 1);
+        let headers = decoder.decode(&[0xbe], 16 * 1024).unwrap();
+        assert_eq!(headers[0].1.len(), 4000);
+
+        // 刚好等于上限时不受影响
+        let block = [0x82, 0x84];
+        let size = ":method".len() + "GET".len() + ":path".len() + "/".len() + 64;
+        assert_eq!(decoder.decode(&block, size).unwrap().len(), 2);
+        assert_eq!(decoder.decode(&block, size - 1), Err(HEADER_LIST_TOO_LARGE));
+    }
+}
@@ -1,21 +1,40 @@
+mod h2;
 mod handler;
+mod hpack;
 mod method;
+mod middleware;
+mod net;
+mod parser;
 mod request;
 mod response;
 mod router;
 mod server;
+mod shutdown;
+mod sse;
+mod state;
 mod state_code;
+#[cfg(feature = "tls")]
+mod tls;
+mod websocket;
 // mod pool;
 
 pub use handler::*;
 pub use method::*;
+pub use middleware::*;
+pub use parser::*;
 pub use request::*;
 pub use response::*;
 pub use router::*;
 pub use server::*;
+pub use shutdown::*;
+pub use sse::*;
+pub use state::*;
 pub use state_code::*;
+pub use websocket::*;
 
 use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
     sync::{mpsc, Arc, Mutex},
     thread,
 };
@@ -55,13 +74,18 @@ impl ThreadPool {
         }
     }
 
-    fn execute<F>(&self, f: F)
+    /// Send a job to the pool, fails if all workers have exited.
+    fn execute<F>(&self, f: F) -> io::Result<()>
     where
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        self.sender
+            .as_ref()
+            .ok_or_else(|| io::Error::other("thread pool is shut down"))?
+            .send(job)
+            .map_err(|_| io::Error::other("thread pool workers have exited"))
     }
 }
 
@@ -94,7 +118,10 @@ impl Worker {
                 Ok(job) => {
                     println!("Worker {id} got a job; executing.");
 
-                    job();
+                    // a panicking handler must not take the worker down with it
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        println!("Worker {id} job panicked; continuing.");
+                    }
                 }
                 Err(_) => {
                     println!("Worker {id} disconnected; shutting down.");
@@ -1,31 +1,60 @@
+use std::fmt::{Display, Formatter, Result};
 
-use std::fmt::{Display, Result, Formatter};
-
-#[derive(Debug, PartialEq, Clone, Copy)]
+/// 请求方法，区分大小写(RFC 9110, 9.1)
+///
+/// 变体按声明顺序排序，`Allow`响应头中的method按此顺序排列
+#[derive(Debug, PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 pub enum Method {
     GET,
     POST,
     PUT,
     DELETE,
-    // PATCH,
-    // OPTIONS,
-    // HEAD,
+    PATCH,
+    HEAD,
+    OPTIONS,
+    TRACE,
+    CONNECT,
+    /// 标准之外的扩展method，如WebDAV的`PROPFIND`
+    Extension(String),
 }
 
-impl Display for Method {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+impl Method {
+    /// 全部标准method
+    pub const STANDARD: [Method; 9] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::PATCH,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::CONNECT,
+    ];
+
+    pub fn as_str(&self) -> &str {
         match self {
-            Method::GET => write!(f, "{}", "GET"),
-            Method::POST => write!(f, "{}", "POST"),
-            Method::PUT => write!(f, "{}", "PUT"),
-            Method::DELETE => write!(f, "{}", "DELETE"),
-            // Method::PATCH => write!(f, "{}", "PATCH"),
-            // Method::OPTIONS => write!(f, "{}", "OPTIONS"),
-            // Method::HEAD => write!(f, "{}", "HEAD"),
+            Method::GET => "GET",
+            Method::POST => "POST",
+            Method::PUT => "PUT",
+            Method::DELETE => "DELETE",
+            Method::PATCH => "PATCH",
+            Method::HEAD => "HEAD",
+            Method::OPTIONS => "OPTIONS",
+            Method::TRACE => "TRACE",
+            Method::CONNECT => "CONNECT",
+            Method::Extension(method) => method,
         }
     }
 }
 
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// 未知的method转换为`Method::Extension`
 impl From<&str> for Method {
     fn from(method: &str) -> Method {
         match method {
@@ -33,10 +62,12 @@ impl From<&str> for Method {
             "POST" => Method::POST,
             "PUT" => Method::PUT,
             "DELETE" => Method::DELETE,
-            // "PATCH" => Method::PATCH,
-            // "OPTIONS" => Method::OPTIONS,
-            // "HEAD" => Method::HEAD,
-            _ => Method::GET,
+            "PATCH" => Method::PATCH,
+            "HEAD" => Method::HEAD,
+            "OPTIONS" => Method::OPTIONS,
+            "TRACE" => Method::TRACE,
+            "CONNECT" => Method::CONNECT,
+            _ => Method::Extension(method.to_string()),
         }
     }
 }
@@ -0,0 +1,136 @@
+use std::{fmt::Debug, sync::Arc};
+
+use crate::{HttpRequest, HttpResponse};
+
+/// 中间件，包裹在处理函数外层执行
+///
+/// 中间件可以修改请求和响应，调用`next.run`继续执行后续的中间件和处理函数，
+/// 不调用则直接返回当前响应(短路)。
+///
+/// 执行顺序：`HttpServer`上注册的中间件 > 根`Router`上注册的中间件 >
+/// 子路由(`nest`/`scope`)上注册的中间件 > 处理函数，同一层级按注册顺序执行，
+/// 先注册的在外层。`HttpServer`上的中间件对所有请求生效(包括404、405)，
+/// `Router`上的中间件只对该Router中匹配成功的route生效。
+///
+/// ```
+/// use httpx::{HttpRequest, HttpResponse, HttpServer, HttpStateCode, Middleware, Next, Router};
+///
+/// struct Auth;
+///
+/// impl Middleware for Auth {
+///     fn handle(&self, req: &mut HttpRequest, resp: &mut HttpResponse, next: Next) {
+///         match req.get_header("Authorization") {
+///             Some(_) => next.run(req, resp),
+///             None => {
+///                 resp.set_http_state_code(HttpStateCode::StatusUnauthorized);
+///             }
+///         }
+///     }
+/// }
+///
+/// let mut router = Router::new();
+/// router.scope("/admin", |admin| {
+///     admin.middleware(Auth);
+///     admin.get("/stats", |_r, w| {
+///         w.write_str("stats");
+///     });
+/// });
+///
+/// let mut server = HttpServer::application();
+/// server
+///     .middleware(|req: &mut HttpRequest, resp: &mut HttpResponse, next: Next| {
+///         next.run(req, resp);
+///         resp.insert_header("X-Powered-By", "httpx");
+///     })
+///     .mount_route(router);
+/// ```
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &mut HttpRequest, response: &mut HttpResponse, next: Next<'_>);
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&mut HttpRequest, &mut HttpResponse, Next<'_>) + Send + Sync,
+{
+    fn handle(&self, request: &mut HttpRequest, response: &mut HttpResponse, next: Next<'_>) {
+        self(request, response, next)
+    }
+}
+
+impl Debug for dyn Middleware {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Middleware")
+    }
+}
+
+/// 调用链中剩余的中间件和最终的处理函数
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Fn(&mut HttpRequest, &mut HttpResponse),
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a [Arc<dyn Middleware>],
+        endpoint: &'a dyn Fn(&mut HttpRequest, &mut HttpResponse),
+    ) -> Self {
+        Next {
+            middleware,
+            endpoint,
+        }
+    }
+
+    /// 执行后续的中间件和处理函数
+    pub fn run(self, request: &mut HttpRequest, response: &mut HttpResponse) {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(request, response, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(request, response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_middleware {
+    use std::sync::{Arc, Mutex};
+
+    use super::{Middleware, Next};
+    use crate::{HttpRequest, HttpResponse};
+
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Trace {
+        fn handle(&self, req: &mut HttpRequest, resp: &mut HttpResponse, next: Next) {
+            self.1.lock().unwrap().push(format!("{} before", self.0));
+            if req.get_header("Stop") == Some(self.0) {
+                return;
+            }
+            next.run(req, resp);
+            self.1.lock().unwrap().push(format!("{} after", self.0));
+        }
+    }
+
+    #[test]
+    fn test_order_and_short_circuit() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let chain: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(Trace("a", log.clone())),
+            Arc::new(Trace("b", log.clone())),
+        ];
+        let l = log.clone();
+        let endpoint = move |_r: &mut HttpRequest, _w: &mut HttpResponse| {
+            l.lock().unwrap().push("handler".to_string());
+        };
+
+        let mut req = HttpRequest::default();
+        Next::new(&chain, &endpoint).run(&mut req, &mut HttpResponse::new());
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["a before", "b before", "handler", "b after", "a after"]
+        );
+
+        log.lock().unwrap().clear();
+        req.insert_header("Stop", "b");
+        Next::new(&chain, &endpoint).run(&mut req, &mut HttpResponse::new());
+        assert_eq!(*log.lock().unwrap(), ["a before", "b before", "a after"]);
+    }
+}
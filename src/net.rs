@@ -0,0 +1,305 @@
+use std::{
+    fmt::{Display, Formatter},
+    io::{self, ErrorKind, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+};
+
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+#[cfg(feature = "tls")]
+use rustls::ServerConfig;
+use socket2::{Domain, Protocol, Socket, Type};
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsStream;
+
+/// Unix domain socket地址的前缀，如`unix:/run/httpx.sock`
+pub(crate) const UNIX_PREFIX: &str = "unix:";
+
+/// 监听socket，TCP或Unix domain socket
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    /// bind创建的socket文件在drop时删除
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        _file: Option<SocketFile>,
+    },
+    /// 接受的TCP连接使用TLS
+    #[cfg(feature = "tls")]
+    Tls(TcpListener, Arc<ServerConfig>),
+}
+
+impl Listener {
+    /// 绑定地址，`unix:`开头的地址绑定为Unix domain socket
+    pub(crate) fn bind(addr: &str) -> io::Result<Listener> {
+        if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+            return Self::bind_unix(path);
+        }
+        let mut last = io::Error::new(ErrorKind::InvalidInput, "no address to bind");
+        for socket_addr in addr.to_socket_addrs()? {
+            match Self::bind_tcp(socket_addr) {
+                Ok(listener) => return Ok(Listener::Tcp(listener)),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+
+    // IPv6地址只监听IPv6(IPV6_V6ONLY)，与同端口的IPv4地址互不冲突，
+    // 同时监听`0.0.0.0:8080`和`[::]:8080`即可实现双栈
+    fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        // 与std的TcpListener::bind保持一致，重启时可以立即重新绑定处于TIME_WAIT的端口
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        Ok(socket.into())
+    }
+
+    #[cfg(unix)]
+    fn bind_unix(path: &str) -> io::Result<Listener> {
+        // 上次退出时遗留的socket文件会导致bind失败，没有进程在监听时删除
+        if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket())
+            && UnixStream::connect(path).is_err_and(|e| e.kind() == ErrorKind::ConnectionRefused)
+        {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        Ok(Listener::Unix {
+            listener,
+            _file: Some(SocketFile(PathBuf::from(path))),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn bind_unix(_path: &str) -> io::Result<Listener> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "unix domain socket is not supported on this platform",
+        ))
+    }
+
+    /// config为Some时，TCP监听socket改为接受TLS连接
+    #[cfg(feature = "tls")]
+    pub(crate) fn with_tls(self, config: Option<&Arc<ServerConfig>>) -> Listener {
+        match (self, config) {
+            (Listener::Tcp(listener), Some(config)) => Listener::Tls(listener, config.clone()),
+            (listener, _) => listener,
+        }
+    }
+
+    pub(crate) fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+            // 握手在处理连接的线程中进行，不阻塞accept
+            #[cfg(feature = "tls")]
+            Listener::Tls(listener, config) => {
+                let (stream, _) = listener.accept()?;
+                let stream = TlsStream::new(config.clone(), stream)?;
+                Ok(Stream::Tls(Box::new(stream)))
+            }
+        }
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<LocalAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(LocalAddr::Tcp),
+            #[cfg(feature = "tls")]
+            Listener::Tls(listener, _) => listener.local_addr().map(LocalAddr::Tcp),
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => {
+                let addr = listener.local_addr()?;
+                let path = addr.as_pathname().ok_or_else(|| {
+                    io::Error::new(ErrorKind::InvalidInput, "unnamed unix socket")
+                })?;
+                Ok(LocalAddr::Unix(path.to_path_buf()))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug)]
+pub(crate) struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// 监听地址
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum LocalAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl LocalAddr {
+    /// 连接一次监听地址，唤醒阻塞在accept上的线程
+    pub(crate) fn wake(&self) {
+        match self {
+            LocalAddr::Tcp(addr) => {
+                let mut addr = *addr;
+                if addr.ip().is_unspecified() {
+                    match addr {
+                        SocketAddr::V4(_) => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+                        SocketAddr::V6(_) => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+                    }
+                }
+                let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+            }
+            #[cfg(unix)]
+            LocalAddr::Unix(path) => {
+                let _ = UnixStream::connect(path);
+            }
+        }
+    }
+}
+
+impl Display for LocalAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocalAddr::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            LocalAddr::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// 客户端连接，TCP或Unix domain socket
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<TlsStream>),
+}
+
+impl Stream {
+    /// TLS连接的会话状态无法复制，返回底层的TCP连接，只能用于关闭连接
+    pub(crate) fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock().try_clone().map(Stream::Tcp),
+        }
+    }
+
+    pub(crate) fn is_tls(&self) -> bool {
+        #[cfg(feature = "tls")]
+        if let Stream::Tls(_) = self {
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock().shutdown(how),
+        }
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock().set_read_timeout(timeout),
+        }
+    }
+
+    /// 关闭Nagle算法，Unix domain socket上没有该选项
+    pub(crate) fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock().set_nodelay(nodelay),
+        }
+    }
+
+    /// 客户端地址，Unix domain socket的客户端通常没有绑定路径，此时返回监听的路径，如`unix:/run/httpx.sock`
+    pub(crate) fn peer_addr(&self) -> io::Result<String> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().map(|addr| addr.to_string()),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.sock().peer_addr().map(|addr| addr.to_string()),
+            #[cfg(unix)]
+            Stream::Unix(stream) => {
+                let peer = stream.peer_addr()?;
+                let local = stream.local_addr()?;
+                let path = peer.as_pathname().or(local.as_pathname());
+                Ok(match path {
+                    Some(path) => format!("{}{}", UNIX_PREFIX, path.display()),
+                    None => UNIX_PREFIX.to_string(),
+                })
+            }
+        }
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => (&**stream).read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => (&**stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => (&**stream).flush(),
+        }
+    }
+}
@@ -0,0 +1,573 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Display, Formatter},
+    io::{self, ErrorKind, Read},
+};
+
+use crate::{request::parse_query, HttpRequest, HttpStateCode, Method, Version};
+
+/// 请求头默认最大长度：8KB
+pub(crate) const DEFAULT_MAX_HEADER_SIZE: usize = 8 * 1024;
+/// 请求体默认最大长度：2MB
+pub(crate) const DEFAULT_MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// 解析请求时可能出现的错误
+#[derive(Debug)]
+pub enum ParseError {
+    /// 请求行或请求头格式错误
+    Malformed(&'static str),
+    /// 不支持的HTTP版本
+    UnsupportedVersion,
+    /// 不支持的Transfer-Encoding
+    UnsupportedEncoding,
+    /// 请求头超出长度限制
+    HeaderTooLarge,
+    /// 请求体超出长度限制
+    BodyTooLarge,
+    /// 请求尚未接收完整连接即被关闭
+    Incomplete,
+    /// 读取连接时出错
+    Io(io::Error),
+}
+
+impl ParseError {
+    /// 返回该错误对应的响应状态码，连接层面的错误无法再写回响应，返回None
+    pub fn state_code(&self) -> Option<HttpStateCode> {
+        match self {
+            ParseError::Malformed(_) => Some(HttpStateCode::StatusBadRequest),
+            ParseError::UnsupportedVersion => Some(HttpStateCode::StatusHTTPVersionNotSupported),
+            ParseError::UnsupportedEncoding => Some(HttpStateCode::StatusNotImplemented),
+            ParseError::HeaderTooLarge => Some(HttpStateCode::StatusRequestHeaderFieldsTooLarge),
+            ParseError::BodyTooLarge => Some(HttpStateCode::StatusRequestEntityTooLarge),
+            ParseError::Incomplete | ParseError::Io(_) => None,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Malformed(reason) => write!(f, "malformed request: {}", reason),
+            ParseError::UnsupportedVersion => write!(f, "unsupported http version"),
+            ParseError::UnsupportedEncoding => write!(f, "unsupported transfer encoding"),
+            ParseError::HeaderTooLarge => write!(f, "request header too large"),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+            ParseError::Incomplete => write!(f, "connection closed before request was complete"),
+            ParseError::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+/// 解析请求时的长度限制
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ParseLimits {
+    pub(crate) max_header_size: usize,
+    pub(crate) max_body_size: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+}
+
+/// 从字节流中逐个读取HTTP/1.x请求
+///
+/// 未消费的数据保留在内部缓冲区中，供读取下一个请求时使用
+pub(crate) struct RequestReader<R> {
+    inner: R,
+    buf: Vec<u8>,
+    limits: ParseLimits,
+}
+
+impl<R: Read> RequestReader<R> {
+    pub(crate) fn new(inner: R, limits: ParseLimits) -> Self {
+        RequestReader {
+            inner,
+            buf: Vec::new(),
+            limits,
+        }
+    }
+
+    /// 读取下一个完整的请求，连接在请求开始前被正常关闭时返回`Ok(None)`
+    pub(crate) fn read_request<'a>(&mut self) -> Result<Option<HttpRequest<'a>>, ParseError> {
+        let head_len = loop {
+            self.skip_empty_lines();
+            if let Some(n) = find_head_end(&self.buf) {
+                break n;
+            }
+            if self.buf.len() > self.limits.max_header_size {
+                return Err(ParseError::HeaderTooLarge);
+            }
+            if self.fill()? == 0 {
+                return match self.buf.is_empty() {
+                    true => Ok(None),
+                    false => Err(ParseError::Incomplete),
+                };
+            }
+        };
+        if head_len > self.limits.max_header_size {
+            return Err(ParseError::HeaderTooLarge);
+        }
+
+        let head: Vec<u8> = self.buf.drain(..head_len).collect();
+        let (mut request, framing) = parse_head(&head)?;
+
+        let body = match framing {
+            Framing::Length(len) => self.read_sized_body(len)?,
+            Framing::Chunked => self.read_chunked_body(&mut request.headers)?,
+        };
+        if !body.is_empty() {
+            request.body = Some(body);
+        }
+        Ok(Some(request))
+    }
+
+    /// 连接上的数据是否以prefix开头，如HTTP/2的连接序言，读取到的数据仍保留在缓冲区中
+    pub(crate) fn starts_with(&mut self, prefix: &[u8]) -> io::Result<bool> {
+        loop {
+            let n = self.buf.len().min(prefix.len());
+            if self.buf[..n] != prefix[..n] {
+                return Ok(false);
+            }
+            if n == prefix.len() {
+                return Ok(true);
+            }
+            if self.fill()? == 0 {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// 切换协议时取回底层连接和已读取但未解析的数据
+    pub(crate) fn into_parts(self) -> (R, Vec<u8>) {
+        (self.inner, self.buf)
+    }
+
+    fn read_sized_body(&mut self, len: usize) -> Result<Vec<u8>, ParseError> {
+        if len > self.limits.max_body_size {
+            return Err(ParseError::BodyTooLarge);
+        }
+        self.fill_to(len)?;
+        Ok(self.buf.drain(..len).collect())
+    }
+
+    // 解码chunked请求体(RFC 9112, 7.1)，trailer字段合并到请求头中
+    fn read_chunked_body(
+        &mut self,
+        headers: &mut HashMap<String, String>,
+    ) -> Result<Vec<u8>, ParseError> {
+        let mut body = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            // 忽略chunk扩展参数
+            let size = line.split(';').next().unwrap_or_default().trim();
+            // from_str_radix接受开头的`+`，chunk-size只允许1*HEXDIG(RFC 9112, 7.1)
+            let size = match !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                true => usize::from_str_radix(size, 16).ok(),
+                false => None,
+            }
+            .ok_or(ParseError::Malformed("invalid chunk size"))?;
+            if size == 0 {
+                break;
+            }
+            if size > self.limits.max_body_size - body.len() {
+                return Err(ParseError::BodyTooLarge);
+            }
+            self.fill_to(size + 2)?;
+            body.extend(self.buf.drain(..size));
+            match self.buf.drain(..2).as_slice() {
+                b"\r\n" => {}
+                _ => return Err(ParseError::Malformed("missing chunk terminator")),
+            }
+        }
+
+        let mut trailer_size = 0;
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            trailer_size += line.len();
+            if trailer_size > self.limits.max_header_size {
+                return Err(ParseError::HeaderTooLarge);
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or(ParseError::Malformed("invalid trailer line"))?;
+            if !is_token(key) {
+                return Err(ParseError::Malformed("invalid trailer name"));
+            }
+            // 与消息分帧相关的字段不允许出现在trailer中
+            if ["Content-Length", "Transfer-Encoding", "Host"]
+                .iter()
+                .any(|k| k.eq_ignore_ascii_case(key))
+            {
+                continue;
+            }
+            insert_header(headers, key, value.trim_matches(|c| c == ' ' || c == '\t'));
+        }
+        Ok(body)
+    }
+
+    // 读取一行(不含换行符)，行长度受请求头长度限制约束
+    fn read_line(&mut self) -> Result<String, ParseError> {
+        loop {
+            if let Some(i) = self.buf.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = self.buf.drain(..=i).collect();
+                let line = line.strip_suffix(b"\n").unwrap_or(&line);
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                return Ok(String::from_utf8_lossy(line).to_string());
+            }
+            if self.buf.len() > self.limits.max_header_size {
+                return Err(ParseError::HeaderTooLarge);
+            }
+            if self.fill()? == 0 {
+                return Err(ParseError::Incomplete);
+            }
+        }
+    }
+
+    fn fill_to(&mut self, len: usize) -> Result<(), ParseError> {
+        while self.buf.len() < len {
+            if self.fill()? == 0 {
+                return Err(ParseError::Incomplete);
+            }
+        }
+        Ok(())
+    }
+
+    // 请求行之前的空行应当被忽略(RFC 9112, 2.2)
+    fn skip_empty_lines(&mut self) {
+        let n = self
+            .buf
+            .iter()
+            .take_while(|&&b| b == b'\r' || b == b'\n')
+            .count();
+        self.buf.drain(..n);
+    }
+
+    fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0; 4096];
+        loop {
+            match self.inner.read(&mut chunk) {
+                Ok(len) => {
+                    self.buf.extend_from_slice(&chunk[..len]);
+                    return Ok(len);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// 返回请求头结束(空行之后)的位置，同时兼容只使用LF换行的客户端
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    for (i, &b) in buf.iter().enumerate() {
+        if b != b'\n' {
+            continue;
+        }
+        match &buf[i + 1..] {
+            [b'\n', ..] => return Some(i + 2),
+            [b'\r', b'\n', ..] => return Some(i + 3),
+            _ => {}
+        }
+    }
+    None
+}
+
+// 请求体的分帧方式
+enum Framing {
+    Length(usize),
+    Chunked,
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+pub(crate) fn insert_header(headers: &mut HashMap<String, String>, key: &str, value: &str) {
+    headers
+        .entry(key.to_string())
+        .and_modify(|v| {
+            v.push_str(", ");
+            v.push_str(value);
+        })
+        .or_insert_with(|| value.to_string());
+}
+
+fn parse_head<'a>(head: &[u8]) -> Result<(HttpRequest<'a>, Framing), ParseError> {
+    let text = String::from_utf8_lossy(head);
+    let mut lines = text
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line));
+
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(m), Some(t), Some(v), None) if is_token(m) && !t.is_empty() => (m, t, v),
+        _ => return Err(ParseError::Malformed("invalid request line")),
+    };
+    let version = match Version::from(version) {
+        v @ (Version::V1_0 | Version::V1_1) => v,
+        _ if version.starts_with("HTTP/") => return Err(ParseError::UnsupportedVersion),
+        _ => return Err(ParseError::Malformed("invalid http version")),
+    };
+    let (uri, params) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers: HashMap<String, String> = HashMap::new();
+    let mut content_length: Option<usize> = None;
+    let mut transfer_encoding: Vec<String> = Vec::new();
+    for line in lines {
+        if line.is_empty() {
+            continue;
+        }
+        // 已废弃的折行格式(RFC 9112, 5.2)
+        if line.starts_with(' ') || line.starts_with('\t') {
+            return Err(ParseError::Malformed("obsolete line folding"));
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or(ParseError::Malformed("invalid header line"))?;
+        if !is_token(key) {
+            return Err(ParseError::Malformed("invalid header name"));
+        }
+        let value = value.trim_matches(|c| c == ' ' || c == '\t');
+
+        if key.eq_ignore_ascii_case("Transfer-Encoding") {
+            transfer_encoding.extend(
+                value
+                    .split(',')
+                    .map(|coding| coding.trim().to_ascii_lowercase())
+                    .filter(|coding| !coding.is_empty()),
+            );
+        }
+        if key.eq_ignore_ascii_case("Content-Length") {
+            for v in value.split(',') {
+                let len = parse_content_length(v.trim())
+                    .ok_or(ParseError::Malformed("invalid content-length"))?;
+                if content_length.is_some_and(|l| l != len) {
+                    return Err(ParseError::Malformed("conflicting content-length"));
+                }
+                content_length = Some(len);
+            }
+        }
+
+        insert_header(&mut headers, key, value);
+    }
+
+    let framing = match (transfer_encoding.last(), content_length) {
+        (None, len) => Framing::Length(len.unwrap_or(0)),
+        // 同时出现时存在请求走私的风险，直接拒绝(RFC 9112, 6.3)
+        (Some(_), Some(_)) => {
+            return Err(ParseError::Malformed(
+                "both transfer-encoding and content-length",
+            ))
+        }
+        (Some(last), None) if last != "chunked" => {
+            return Err(ParseError::Malformed(
+                "final transfer-coding is not chunked",
+            ))
+        }
+        // 仅支持chunked，不支持gzip等压缩编码
+        (Some(_), None) if transfer_encoding.len() > 1 => {
+            return Err(ParseError::UnsupportedEncoding)
+        }
+        (Some(_), None) => Framing::Chunked,
+    };
+
+    let request = HttpRequest {
+        method: Method::from(method),
+        uri: uri.to_string(),
+        version,
+        headers,
+        body: None,
+        more: HashMap::new(),
+        params: Some(params.to_string()),
+        query: parse_query(params),
+        ..HttpRequest::default()
+    };
+    Ok((request, framing))
+}
+
+// Content-Length只允许1*DIGIT(RFC 9112, 6.3)，parse会接受开头的`+`，宽松的解析可能导致请求走私
+pub(crate) fn parse_content_length(value: &str) -> Option<usize> {
+    match !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        true => value.parse().ok(),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test_parser {
+    use std::io::Read;
+
+    use super::{ParseError, ParseLimits, RequestReader};
+    use crate::{HttpStateCode, Method, Version};
+
+    // 每次只返回一个字节，模拟数据被拆分成多个包到达
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.split_first() {
+                Some((b, rest)) if !buf.is_empty() => {
+                    buf[0] = *b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn parse(data: &[u8]) -> Result<Option<crate::HttpRequest<'static>>, ParseError> {
+        RequestReader::new(data, ParseLimits::default()).read_request()
+    }
+
+    #[test]
+    fn test_split_packets() {
+        let data = b"POST /upload?x=1 HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+        let mut reader = RequestReader::new(Trickle(data), ParseLimits::default());
+        let request = reader.read_request().unwrap().unwrap();
+        assert_eq!(request.method, Method::POST);
+        assert_eq!(request.uri, "/upload");
+        assert_eq!(request.get_params(), Some("x=1"));
+        assert_eq!(request.get_header("host"), Some("a"));
+        assert_eq!(request.get_body(), Some("hello"));
+        assert!(reader.read_request().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_binary_body_and_pipelining() {
+        let mut data = b"PUT /a HTTP/1.1\r\nContent-Length: 4\r\n\r\n".to_vec();
+        data.extend_from_slice(&[0xff, 0x00, b'\r', b'\n']);
+        data.extend_from_slice(b"GET /b HTTP/1.0\n\n");
+        let mut reader = RequestReader::new(data.as_slice(), ParseLimits::default());
+        let first = reader.read_request().unwrap().unwrap();
+        assert_eq!(
+            first.get_body_bytes(),
+            Some(&[0xff, 0x00, b'\r', b'\n'][..])
+        );
+        let second = reader.read_request().unwrap().unwrap();
+        assert_eq!(second.uri, "/b");
+        assert_eq!(second.version, Version::V1_0);
+        assert_eq!(second.body, None);
+    }
+
+    #[test]
+    fn test_errors() {
+        let code = |data: &[u8]| parse(data).unwrap_err().state_code();
+        let bad = Some(HttpStateCode::StatusBadRequest);
+        assert_eq!(code(b"GET /\r\n\r\n"), bad);
+        assert_eq!(code(b"GET  / HTTP/1.1\r\n\r\n"), bad);
+        assert_eq!(code(b"GET / HTTP/1.1\r\nNo-Colon\r\n\r\n"), bad);
+        assert_eq!(code(b"GET / HTTP/1.1\r\nBad Name: x\r\n\r\n"), bad);
+        assert_eq!(code(b"GET / HTTP/1.1\r\nA: b\r\n  c\r\n\r\n"), bad);
+        assert_eq!(code(b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), bad);
+        assert_eq!(code(b"GET / HTTP/1.1\r\nContent-Length: +5\r\n\r\n"), bad);
+        assert_eq!(code(b"GET / HTTP/1.1\r\nContent-Length: -0\r\n\r\n"), bad);
+        assert_eq!(
+            code(b"GET / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"),
+            bad
+        );
+        assert_eq!(
+            code(b"GET / HTTP/3.0\r\n\r\n"),
+            Some(HttpStateCode::StatusHTTPVersionNotSupported)
+        );
+        assert_eq!(
+            code(b"POST / HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n"),
+            Some(HttpStateCode::StatusRequestEntityTooLarge)
+        );
+        let mut huge = b"GET / HTTP/1.1\r\nX: ".to_vec();
+        huge.extend(std::iter::repeat_n(b'a', 10 * 1024));
+        assert_eq!(
+            code(&huge),
+            Some(HttpStateCode::StatusRequestHeaderFieldsTooLarge)
+        );
+        assert!(matches!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc"),
+            Err(ParseError::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn test_chunked_body() {
+        let data = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;ext=1\r\nhello\r\n8\r\n\r\nworld\n\r\n0\r\nChecksum: abc\r\n\r\nGET /next HTTP/1.1\r\n\r\n";
+        let mut reader = RequestReader::new(Trickle(data), ParseLimits::default());
+        let request = reader.read_request().unwrap().unwrap();
+        assert_eq!(request.get_body(), Some("hello\r\nworld\n"));
+        assert_eq!(request.get_header("Checksum"), Some("abc"));
+        let next = reader.read_request().unwrap().unwrap();
+        assert_eq!(next.uri, "/next");
+    }
+
+    #[test]
+    fn test_chunked_errors() {
+        let code = |data: &[u8], limits: ParseLimits| {
+            RequestReader::new(data, limits)
+                .read_request()
+                .unwrap_err()
+                .state_code()
+        };
+        let limits = ParseLimits::default();
+        let bad = Some(HttpStateCode::StatusBadRequest);
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let chunked = |body: &str| format!("{}{}", head, body).into_bytes();
+        assert_eq!(code(&chunked("zz\r\n"), limits), bad);
+        assert_eq!(code(&chunked("+a\r\n"), limits), bad);
+        assert_eq!(code(&chunked("3\r\nabcd\r\n0\r\n\r\n"), limits), bad);
+        assert_eq!(
+            code(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n",
+                limits
+            ),
+            bad
+        );
+        assert_eq!(
+            code(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+                limits
+            ),
+            bad
+        );
+        assert_eq!(
+            code(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n",
+                limits
+            ),
+            Some(HttpStateCode::StatusNotImplemented)
+        );
+        let small = ParseLimits {
+            max_body_size: 8,
+            ..limits
+        };
+        assert_eq!(
+            code(&chunked("5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"), small),
+            Some(HttpStateCode::StatusRequestEntityTooLarge)
+        );
+    }
+}
@@ -1,6 +1,15 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Display, Formatter},
+    str::FromStr,
+    sync::Arc,
+};
 
-use crate::Method;
+use crate::{
+    parser::{ParseLimits, RequestReader},
+    AppState, HttpStateCode, Method, ParseError,
+};
 
 pub trait HttpRequestExtend {
     fn set_remote_addr(&mut self, addr: &str);
@@ -8,8 +17,9 @@ pub trait HttpRequestExtend {
 }
 
 // PartialEq:方便在Test中进行对比,否则assert_eq将不能对该类型进行断言对比
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Version {
+    V1_0,
     V1_1,
     V2_0,
     NoSupport,
@@ -18,6 +28,7 @@ pub enum Version {
 impl From<&str> for Version {
     fn from(version: &str) -> Version {
         match version {
+            "HTTP/1.0" => Version::V1_0,
             "HTTP/1.1" => Version::V1_1,
             "HTTP/2.0" => Version::V2_0,
             _ => Version::NoSupport,
@@ -26,6 +37,7 @@ impl From<&str> for Version {
 }
 
 // 获取 Content-Type 用于解析请求参数
+#[allow(dead_code, clippy::upper_case_acronyms)]
 enum ContentType {
     JSON,
     HTML,
@@ -70,6 +82,7 @@ mod test_version {
     use super::*;
     #[test]
     fn test_version() {
+        assert_eq!(Version::from("HTTP/1.0"), Version::V1_0);
         assert_eq!(Version::from("HTTP/1.1"), Version::V1_1);
         assert_eq!(Version::from("HTTP/2.0"), Version::V2_0);
         assert_eq!(Version::from("HTTP/3.0"), Version::NoSupport);
@@ -81,7 +94,10 @@ mod test_version {
         assert_eq!(Method::from("POST"), Method::POST);
         assert_eq!(Method::from("PUT"), Method::PUT);
         assert_eq!(Method::from("DELETE"), Method::DELETE);
-        // assert_eq!(Method::from("TEST"), Method::NoSupport);
+        assert_eq!(Method::from("PATCH"), Method::PATCH);
+        assert_eq!(Method::from("OPTIONS"), Method::OPTIONS);
+        assert_eq!(Method::from("get"), Method::Extension("get".to_string()));
+        assert_eq!(Method::from("PROPFIND").to_string(), "PROPFIND");
     }
 
     #[test]
@@ -100,102 +116,49 @@ pub struct HttpRequest<'a> {
     pub(crate) uri: String,
     pub(crate) version: Version,
     pub(crate) headers: HashMap<String, String>,
-    pub(crate) body: Option<String>,
+    pub(crate) body: Option<Vec<u8>>,
     pub(crate) more: HashMap<&'a str, String>,
     pub(crate) params: Option<String>,
+    // 按出现顺序保存解码后的查询参数
+    pub(crate) query: Vec<(String, String)>,
+    pub(crate) state: Arc<AppState>,
+    pub(crate) path_params: HashMap<String, String>,
 }
 
-impl<'a> From<String> for HttpRequest<'a> {
-    fn from(request: String) -> Self {
-        let request_default = HttpRequest::default();
-        if request.contains("HTTP/") {
-            return HttpRequest::parse_request(&request);
-        }
-        request_default
+impl<'a> TryFrom<String> for HttpRequest<'a> {
+    type Error = ParseError;
+
+    fn try_from(request: String) -> Result<Self, ParseError> {
+        HttpRequest::parse(request.as_bytes())
     }
 }
 
 impl<'a> HttpRequest<'a> {
-    fn parse_request(request: &str) -> Self {
-        // 空行之后是body内容
-        let mut empty_line = false;
-        let mut body: Option<String> = None;
-        let lines = request.lines().collect::<Vec<&str>>();
-        let mut headers: HashMap<String, String> = HashMap::new();
-        let mut line_iter = lines.iter();
-        let protol_header = line_iter.next().unwrap();
-        let mut protol_headers = protol_header.split_whitespace();
-        let method = protol_headers.next().unwrap();
-        let rescues = protol_headers.next().unwrap();
-        let version = protol_headers.next().unwrap();
-
-        let uri = match rescues.split("?").clone().nth(0) {
-            Some(s) => s,
-            None => "",
-        };
-        let params = match rescues.split("?").clone().nth(1) {
-            Some(s) => s,
-            None => "",
-        };
-        // println!("{uri} {params}");
-        // println!("{:?}", line_iter);
-
-        let mut s: Vec<String> = Vec::new();
-
-        for line in line_iter {
-            if line.is_empty() || line.len() == 0 {
-                empty_line = true;
-                continue;
-            }
-            if empty_line {
-                // format!("{}{}", "xxx_", line);
-                // s += line.to_string();
-                s.push(line.to_string());
-                // body = Some(line.to_string());
-                continue;
-            }
-            let mut headers_iter = line.splitn(2, ':');
-            let key = headers_iter.next().unwrap();
-            let value = headers_iter.next().unwrap();
-            headers.insert(key.to_string(), value.trim().to_string());
-        }
-
-        if s.len() > 0 {
-            body = Some(s.join(""));
-        }
-
-        // println!(
-        //     "s: {:?}",
-        //     format!("method = {method}, uri = {rescues}, version = {version}")
-        // );
-        // println!("headers: {:?}", headers);
-        // println!("body: {:?}", body);
-
-        // line_iter.as_slice().split_inclusive(pred)
-        // HttpRequest {
-        //     method: method.into(),
-        //     uri: rescues.into(),
-        //     version: version.into(),
-        //     headers: headers,
-        //     body,
-        //     more: HashMap::new(),
-        // }
-        HttpRequest {
-            method: method.into(),
-            uri: uri.to_string(),
-            version: version.into(),
-            headers: headers,
-            body,
-            more: HashMap::new(),
-            params: Some(params.to_string()),
-        }
+    /// 从完整的请求报文中解析出HttpRequest
+    pub fn parse(request: &[u8]) -> Result<Self, ParseError> {
+        RequestReader::new(request, ParseLimits::default())
+            .read_request()?
+            .ok_or(ParseError::Incomplete)
     }
 }
 
 #[warn(dead_code)]
 impl<'a> HttpRequest<'a> {
+    /// 获取请求头，key不区分大小写
     pub fn get_header(&self, key: &str) -> Option<&str> {
-        self.headers.get(key).map(|value| value.as_str())
+        match self.headers.get(key) {
+            Some(value) => Some(value.as_str()),
+            None => self
+                .headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.as_str()),
+        }
+    }
+    /// 设置请求头，供中间件修改请求使用
+    pub fn insert_header(&mut self, key: &str, value: &str) -> &mut Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
     }
     pub fn get_uri(&self) -> &str {
         self.uri.as_str()
@@ -203,16 +166,83 @@ impl<'a> HttpRequest<'a> {
     pub fn get_header_all(&self) -> &HashMap<String, String> {
         &self.headers
     }
+    /// 以字符串形式获取请求体，请求体不是合法的UTF-8时返回None
     pub fn get_body(&self) -> Option<&str> {
-        self.body.as_ref().map(|value| value.as_str())
+        self.body
+            .as_deref()
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+    /// 获取原始请求体
+    pub fn get_body_bytes(&self) -> Option<&[u8]> {
+        self.body.as_deref()
     }
+    /// 获取`?`之后未解码的原始查询字符串
     pub fn get_params(&self) -> Option<&str> {
-        self.params.as_ref().map(|value| value.as_str())
+        self.params.as_deref()
+    }
+
+    /// 获取查询参数，同名参数出现多次时返回第一个，值已进行百分号解码，`+`解码为空格
+    ///
+    /// ```
+    /// use httpx::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/search", |r, w| {
+    ///     let page: u32 = r.query("page").and_then(|p| p.parse().ok()).unwrap_or(1);
+    ///     let tags = r.query_all("tag").join(",");
+    ///     w.write_str(&format!("{} page {} tags {}", r.query("q").unwrap_or_default(), page, tags));
+    /// });
+    /// ```
+    pub fn query(&self, key: &str) -> Option<&str> {
+        self.query_pairs().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// 获取同名查询参数的全部值，按出现顺序排列，如`?tag=a&tag=b`
+    pub fn query_all(&self, key: &str) -> Vec<&str> {
+        self.query_pairs()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+            .collect()
+    }
+
+    /// 按出现顺序遍历全部查询参数
+    pub fn query_pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.query.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// 将查询字符串反序列化为指定类型，需启用`serde` feature，
+    /// 失败时可通过`QueryError::state_code`返回400
+    ///
+    /// ```
+    /// use httpx::Router;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Search {
+    ///     q: String,
+    ///     page: Option<u32>,
+    /// }
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/search", |r, w| match r.query_as::<Search>() {
+    ///     Ok(search) => {
+    ///         w.write_str(&format!("{} page {}", search.q, search.page.unwrap_or(1)));
+    ///     }
+    ///     Err(e) => {
+    ///         w.html(&e.to_string(), e.state_code());
+    ///     }
+    /// });
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn query_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, QueryError> {
+        serde_urlencoded::from_str(self.get_params().unwrap_or_default())
+            .map_err(|e| QueryError(e.to_string()))
     }
     pub fn set_remote_addr(&mut self, addr: &str) {
         self.more.insert("remote_addr", addr.to_owned());
     }
 
+    /// 客户端地址，TCP连接为"ip:port"，Unix domain socket连接为"unix:"加socket路径
     pub fn get_remote_addr(&self) -> String {
         self.more
             .get("remote_addr")
@@ -221,10 +251,142 @@ impl<'a> HttpRequest<'a> {
     }
 
     pub fn get_method(&self) -> Method {
-        self.method
+        self.method.clone()
+    }
+
+    /// 获取路由中`:name`段匹配到的路径参数(已进行百分号解码)
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.path_params.get(name).map(|value| value.as_str())
+    }
+
+    /// 获取全部路径参数
+    pub fn get_param_all(&self) -> &HashMap<String, String> {
+        &self.path_params
+    }
+
+    /// 将路径参数解析为指定类型，转换失败时可通过`ParamError::state_code`返回400
+    ///
+    /// ```
+    /// use httpx::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/users/:id", |r, w| {
+    ///     let id: u64 = match r.param_as("id") {
+    ///         Ok(id) => id,
+    ///         Err(e) => {
+    ///             w.html(&e.to_string(), e.state_code());
+    ///             return;
+    ///         }
+    ///     };
+    ///     w.write_str(&format!("user {}", id));
+    /// });
+    /// ```
+    pub fn param_as<T: FromStr>(&self, name: &str) -> Result<T, ParamError> {
+        let value = self
+            .param(name)
+            .ok_or_else(|| ParamError::Missing(name.to_string()))?;
+        value.parse::<T>().map_err(|_| ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// 按类型获取通过`HttpServer::set_state`注册的共享状态
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get::<T>()
+    }
+}
+
+/// 获取路径参数时可能出现的错误
+#[derive(Debug, PartialEq)]
+pub enum ParamError {
+    /// 路由中没有该参数
+    Missing(String),
+    /// 参数值无法转换为目标类型
+    Invalid { name: String, value: String },
+}
+
+impl ParamError {
+    /// 返回该错误对应的响应状态码
+    pub fn state_code(&self) -> HttpStateCode {
+        match self {
+            // 路由中未声明该参数属于代码错误
+            ParamError::Missing(_) => HttpStateCode::StatusInternalServerError,
+            ParamError::Invalid { .. } => HttpStateCode::StatusBadRequest,
+        }
+    }
+}
+
+impl Display for ParamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "missing path parameter {}", name),
+            ParamError::Invalid { name, value } => {
+                write!(f, "invalid value {:?} for path parameter {}", value, name)
+            }
+        }
+    }
+}
+
+impl Error for ParamError {}
+
+/// 查询字符串无法反序列化为目标类型，见`HttpRequest::query_as`
+#[cfg(feature = "serde")]
+#[derive(Debug, PartialEq)]
+pub struct QueryError(String);
+
+#[cfg(feature = "serde")]
+impl QueryError {
+    /// 返回该错误对应的响应状态码
+    pub fn state_code(&self) -> HttpStateCode {
+        HttpStateCode::StatusBadRequest
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid query string: {}", self.0)
     }
 }
 
+#[cfg(feature = "serde")]
+impl Error for QueryError {}
+
+// 百分号解码，非法的转义序列原样保留
+pub(crate) fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    let hex = |i: usize| bytes.get(i).and_then(|&b| (b as char).to_digit(16));
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let (Some(h), Some(l)) = (hex(i + 1), hex(i + 2)) {
+                out.push((h * 16 + l) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+// 按application/x-www-form-urlencoded格式解析查询字符串，`+`表示空格，
+// 只有key没有`=`的参数值为空字符串
+pub(crate) fn parse_query(query: &str) -> Vec<(String, String)> {
+    let decode = |s: &str| percent_decode(&s.replace('+', " "));
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
 impl<'a> Default for HttpRequest<'a> {
     fn default() -> Self {
         HttpRequest {
@@ -238,7 +400,10 @@ impl<'a> Default for HttpRequest<'a> {
             },
             body: None,
             more: HashMap::new(),
-            params: Some("".to_string())
+            params: Some("".to_string()),
+            query: Vec::new(),
+            state: Arc::new(AppState::new()),
+            path_params: HashMap::new(),
         }
     }
 }
@@ -248,31 +413,112 @@ mod test_http_request {
     use std::collections::HashMap;
 
     use crate::{
-        Method,
         request::{HttpRequest, Version},
+        Method, ParseError,
     };
 
     #[test]
     fn test_parse_request() {
-        let request_str = "GET / HTTP/1.1\r\n";
-        let request = HttpRequest::from(request_str.to_string());
+        let request_str = "GET / HTTP/1.1\r\n\r\n";
+        let request = HttpRequest::try_from(request_str.to_string()).unwrap();
         assert_eq!(request.method, Method::GET);
         assert_eq!(request.uri, "/".to_string());
         assert_eq!(request.version, Version::V1_1);
+
+        // 格式错误的请求返回ParseError，不会被当作默认请求处理
+        let malformed = "GET / HTTP/1.1\r\nContent-Length: +4\r\n\r\nbody";
+        assert!(matches!(
+            HttpRequest::try_from(malformed.to_string()),
+            Err(ParseError::Malformed(_))
+        ));
         assert_eq!(request.headers, HashMap::new());
         assert_eq!(request.body, None);
     }
 
     #[test]
     fn test_parse_request_header_and_body() {
-        let request_str = "GET / HTTP/1.1\r\nContent-Type: text/html\r\n\r\nbody";
-        let request = HttpRequest::from(request_str.to_string());
+        let request_str =
+            "GET / HTTP/1.1\r\nContent-Type: text/html\r\nContent-Length: 4\r\n\r\nbody";
+        let request = HttpRequest::try_from(request_str.to_string()).unwrap();
         let mut header = HashMap::new();
         header.insert("Content-Type".to_string(), "text/html".to_string());
+        header.insert("Content-Length".to_string(), "4".to_string());
         assert_eq!(request.method, Method::GET);
         assert_eq!(request.uri, "/".to_string());
         assert_eq!(request.version, Version::V1_1);
         assert_eq!(request.headers, header);
-        assert_eq!(request.body, Some("body".to_string()));
+        assert_eq!(request.get_body(), Some("body"));
+    }
+
+    #[test]
+    fn test_param_as() {
+        use crate::{HttpStateCode, ParamError};
+
+        let mut request = HttpRequest::default();
+        request
+            .path_params
+            .insert("id".to_string(), "42".to_string());
+        request
+            .path_params
+            .insert("name".to_string(), "abc".to_string());
+        assert_eq!(request.param("id"), Some("42"));
+        assert_eq!(request.param_as::<u32>("id"), Ok(42));
+
+        let err = request.param_as::<u32>("name").unwrap_err();
+        assert_eq!(err.state_code(), HttpStateCode::StatusBadRequest);
+        assert_eq!(
+            request.param_as::<u32>("age"),
+            Err(ParamError::Missing("age".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        let request =
+            HttpRequest::try_from("GET /s?q=rust+http&tag=a&tag=b%2Bc&empty&=x&%E4%BD%A0=%E5%A5%BD&&flag= HTTP/1.1\r\n\r\n".to_string()).unwrap();
+        assert_eq!(request.query("q"), Some("rust http"));
+        assert_eq!(request.query("tag"), Some("a"));
+        assert_eq!(request.query_all("tag"), ["a", "b+c"]);
+        assert_eq!(request.query("empty"), Some(""));
+        assert_eq!(request.query("你"), Some("好"));
+        assert_eq!(request.query("missing"), None);
+        assert!(request.query_all("missing").is_empty());
+        let keys: Vec<&str> = request.query_pairs().map(|(k, _)| k).collect();
+        assert_eq!(keys, ["q", "tag", "tag", "empty", "", "你", "flag"]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_query_as() {
+        use crate::HttpStateCode;
+
+        #[derive(Debug, PartialEq, serde::Deserialize)]
+        struct Search {
+            q: String,
+            page: Option<u32>,
+        }
+
+        let request =
+            HttpRequest::try_from("GET /s?q=a+b%21&page=2 HTTP/1.1\r\n\r\n".to_string()).unwrap();
+        assert_eq!(
+            request.query_as::<Search>(),
+            Ok(Search {
+                q: "a b!".to_string(),
+                page: Some(2),
+            })
+        );
+        let request = HttpRequest::try_from("GET /s?page=x HTTP/1.1\r\n\r\n".to_string()).unwrap();
+        let err = request.query_as::<Search>().unwrap_err();
+        assert_eq!(err.state_code(), HttpStateCode::StatusBadRequest);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        use crate::request::percent_decode;
+
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("%E4%BD%A0%E5%A5%BD"), "你好");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz+"), "%zz+");
     }
 }
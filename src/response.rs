@@ -1,35 +1,79 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, BufWriter, ErrorKind, Read, Write},
+    time::Duration,
+};
 
-use crate::HttpStateCode;
+use crate::{sse, websocket::Upgrade, EventSender, HttpStateCode, ShutdownHandle, Version};
 
 pub trait StateCode<T> {
     fn set_http_state_code(&mut self, state_code: T) -> &mut Self;
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum Version {
-    V1_1,
-    V2_0,
-    NoSupport,
-}
-
 impl From<Version> for String {
     fn from(v: Version) -> Self {
-        return match v {
+        match v {
+            Version::V1_0 => "HTTP/1.0".to_string(),
             Version::V1_1 => "HTTP/1.1".to_string(),
             Version::V2_0 => "HTTP/2.0".to_string(),
             Version::NoSupport => "HTTP/3.0".to_string(),
-        };
+        }
+    }
+}
+
+/// 响应体
+pub enum Body {
+    /// 未设置响应体，发送时以状态码描述作为响应体
+    Empty,
+    /// 内存中的完整数据
+    Bytes(Vec<u8>),
+    /// 从Read中流式读取，长度未知时使用chunked编码发送
+    Reader(Box<dyn Read + Send>, Option<u64>),
+    /// 逐块产生的数据，使用chunked编码发送
+    Chunks(Box<dyn Iterator<Item = Vec<u8>> + Send>),
+}
+
+impl Body {
+    /// 返回响应体长度，流式响应体长度未知时返回None
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader(_, len) => *len,
+            Body::Chunks(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+impl Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Body::Reader(_, len) => write!(f, "Reader({:?})", len),
+            Body::Chunks(_) => write!(f, "Chunks"),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct HttpResponse {
     // connection: &'a mut TcpStream,
     pub version: Version,
     pub status_code: u16,
     pub headers: HashMap<String, String>,
-    pub body: Option<String>,
+    pub body: Body,
+    // WebSocket握手成功时由`Router::websocket`设置
+    pub(crate) upgrade: Option<Upgrade>,
+    // 由server在调用处理函数前设置，停止server时结束Server-Sent Events
+    pub(crate) shutdown: Option<ShutdownHandle>,
+    // 响应体为Server-Sent Events，需要在线程池之外发送
+    pub(crate) event_stream: bool,
 }
 
 /// 为HttpResponse加入默认实现
@@ -43,7 +87,10 @@ impl Default for HttpResponse {
                 header.insert("Content-Type".to_string(), "text/html".to_string());
                 header
             },
-            body: None,
+            body: Body::Empty,
+            upgrade: None,
+            shutdown: None,
+            event_stream: false,
         }
     }
 }
@@ -57,9 +104,81 @@ impl HttpResponse {
         self
     }
     pub fn write_str(&mut self, body: &str) -> &mut Self {
-        self.body = Some(body.to_string());
+        self.body = Body::Bytes(body.as_bytes().to_vec());
         self
     }
+
+    /// 写入二进制响应体，Content-Length按字节数计算
+    pub fn write_bytes<B: Into<Vec<u8>>>(&mut self, body: B) -> &mut Self {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// 从reader中流式读取响应体，length为None时使用chunked编码发送
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use httpx::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/report", |_r, w| {
+    ///     let file = File::open("report.csv").unwrap();
+    ///     let len = file.metadata().ok().map(|m| m.len());
+    ///     w.insert_header("Content-Type", "text/csv");
+    ///     w.write_reader(file, len);
+    /// });
+    /// ```
+    pub fn write_reader<R>(&mut self, reader: R, length: Option<u64>) -> &mut Self
+    where
+        R: Read + Send + 'static,
+    {
+        self.body = Body::Reader(Box::new(reader), length);
+        self
+    }
+
+    /// 逐块发送响应体，使用chunked编码，不会在内存中缓存完整的响应体
+    pub fn write_chunks<I>(&mut self, chunks: I) -> &mut Self
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        self.body = Body::Chunks(Box::new(chunks.into_iter()));
+        self
+    }
+
+    /// 以Server-Sent Events(`text/event-stream`)响应，返回推送事件的EventSender
+    ///
+    /// 处理函数返回后连接保持打开，事件使用chunked编码逐个发送，全部EventSender被drop或停止server后响应结束。
+    /// 没有事件时每隔heartbeat发送一个注释行，用于保持连接和及时发现客户端断开，为0时不发送心跳
+    ///
+    /// ```no_run
+    /// use std::{thread, time::Duration};
+    /// use httpx::{Event, Router};
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/events", |_r, w| {
+    ///     let events = w.event_stream(Duration::from_secs(15));
+    ///     thread::spawn(move || {
+    ///         for i in 0.. {
+    ///             let event = Event::new(&i.to_string()).event("tick").id(&i.to_string());
+    ///             if events.send(event).is_err() {
+    ///                 break;
+    ///             }
+    ///             thread::sleep(Duration::from_secs(1));
+    ///         }
+    ///     });
+    /// });
+    /// ```
+    pub fn event_stream(&mut self, heartbeat: Duration) -> EventSender {
+        let (sender, events) = sse::channel(heartbeat, self.shutdown.clone());
+        self.event_stream = true;
+        self.insert_header("Content-Type", "text/event-stream");
+        self.insert_header("Cache-Control", "no-cache");
+        self.set_http_state_code(HttpStateCode::StatusOK);
+        self.write_chunks(events);
+        sender
+    }
+
     pub fn insert_header(&mut self, key: &str, value: &str) -> &mut Self {
         self.headers.insert(key.to_string(), value.to_string());
         self
@@ -78,40 +197,166 @@ impl HttpResponse {
         self.write_str(body);
         self
     }
-}
 
-impl From<HttpResponse> for String {
-    fn from(http_response: HttpResponse) -> Self {
-        let http_code: u16 = http_response.status_code;
-        let tmp: HttpStateCode = http_response.status_code.into();
-        let code_text: String = tmp.into();
-        let mut response_str = format!(
+    /// 返回图片、protobuf、gzip等二进制数据
+    pub fn binary<B: Into<Vec<u8>>>(
+        &mut self,
+        body: B,
+        content_type: &str,
+        status: HttpStateCode,
+    ) -> &mut Self {
+        self.insert_header("Content-Type", content_type);
+        self.set_http_state_code(status);
+        self.write_bytes(body);
+        self
+    }
+
+    /// 将响应写入w，流式响应体边读取边发送
+    pub fn write_to<W: Write>(self, w: W) -> io::Result<()> {
+        self.send(w, true, true)
+    }
+
+    // chunked为false时(HTTP/1.0客户端)，长度未知的响应体直接写出，由关闭连接标识结束
+    // with_body为false时(HEAD请求)，只发送与完整响应相同的响应头
+    pub(crate) fn send<W: Write>(mut self, w: W, chunked: bool, with_body: bool) -> io::Result<()> {
+        let mut w = BufWriter::new(w);
+        let code_text: String = HttpStateCode::from(self.status_code).into();
+        // HTTP/2的响应不使用文本格式，状态行中只会出现HTTP/1.x
+        let version = match self.version {
+            Version::V1_0 => Version::V1_0,
+            _ => Version::V1_1,
+        };
+        write!(
+            w,
             "{} {} {}\r\n",
-            String::from(http_response.version),
-            http_code,
+            String::from(version),
+            self.status_code,
             code_text
-        );
-        for (key, value) in http_response.headers.iter() {
-            response_str.push_str(&format!("{}: {}\r\n", key, value));
+        )?;
+        for (key, value) in self.headers.iter() {
+            if key.eq_ignore_ascii_case("Content-Length")
+                || key.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                continue;
+            }
+            write!(w, "{}: {}\r\n", key, value)?;
+        }
+
+        let body = self.take_body();
+        match (self.content_length(&body), body.len()) {
+            (Some(len), _) => write!(w, "Content-Length: {}\r\n\r\n", len)?,
+            (None, None) if chunked => write!(w, "Transfer-Encoding: chunked\r\n\r\n")?,
+            _ => write!(w, "\r\n")?,
+        }
+        if !with_body {
+            return w.flush();
+        }
+
+        match body {
+            Body::Empty => {}
+            Body::Bytes(bytes) => w.write_all(&bytes)?,
+            Body::Reader(reader, Some(len)) => {
+                let copied = io::copy(&mut reader.take(len), &mut w)?;
+                if copied < len {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "response body shorter than its length",
+                    ));
+                }
+            }
+            Body::Reader(mut reader, None) => {
+                let mut buf = [0; 8192];
+                loop {
+                    let n = match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => n,
+                        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    Self::write_chunk(&mut w, &buf[..n], chunked)?;
+                }
+                if chunked {
+                    w.write_all(b"0\r\n\r\n")?;
+                }
+            }
+            Body::Chunks(chunks) => {
+                for chunk in chunks {
+                    Self::write_chunk(&mut w, &chunk, chunked)?;
+                }
+                if chunked {
+                    w.write_all(b"0\r\n\r\n")?;
+                }
+            }
         }
-        let content_length = http_response
-            .body
-            .as_ref()
-            .map(|value| value.len())
-            .unwrap_or(code_text.len());
-        response_str.push_str(&format!("Content-Length: {}\r\n", content_length));
-        response_str.push_str("\r\n");
-        if let Some(body) = http_response.body {
-            response_str.push_str(&body);
+        w.flush()
+    }
+
+    // 取出实际发送的响应体，1xx、204、304响应不允许携带响应体(RFC 9110, 6.4.1)
+    pub(crate) fn take_body(&mut self) -> Body {
+        match self.status_code {
+            100..=199 | 204 | 304 => Body::Bytes(Vec::new()),
+            _ => match std::mem::replace(&mut self.body, Body::Empty) {
+                Body::Empty => {
+                    let code_text: String = HttpStateCode::from(self.status_code).into();
+                    Body::Bytes(code_text.into_bytes())
+                }
+                body => body,
+            },
+        }
+    }
+
+    // 响应头中Content-Length的值，None表示不发送(RFC 9110, 8.6)：1xx、204不允许携带，
+    // 304只在处理函数显式设置时原样发送，其余响应在长度已知时发送
+    pub(crate) fn content_length(&self, body: &Body) -> Option<String> {
+        match self.status_code {
+            100..=199 | 204 => None,
+            304 => self
+                .headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+                .map(|(_, value)| value.clone()),
+            _ => body.len().map(|len| len.to_string()),
+        }
+    }
+
+    fn write_chunk<W: Write>(w: &mut W, data: &[u8], chunked: bool) -> io::Result<()> {
+        // 长度为0的块会被当作结束标志，需跳过
+        if data.is_empty() {
+            return Ok(());
+        }
+        if chunked {
+            write!(w, "{:x}\r\n", data.len())?;
+            w.write_all(data)?;
+            w.write_all(b"\r\n")?;
         } else {
-            response_str.push_str(&code_text);
+            w.write_all(data)?;
         }
-        return response_str;
+        // 每块立即发送，不等待缓冲区写满
+        w.flush()
+    }
+}
+
+impl From<HttpResponse> for Vec<u8> {
+    fn from(http_response: HttpResponse) -> Self {
+        let mut buf = Vec::new();
+        // 写入Vec不会失败，流式响应体读取出错时只保留已读取的部分
+        let _ = http_response.write_to(&mut buf);
+        buf
+    }
+}
+
+/// 非UTF-8的响应体会被有损转换，二进制响应请使用`Vec<u8>`
+impl From<HttpResponse> for String {
+    fn from(http_response: HttpResponse) -> Self {
+        let buf: Vec<u8> = http_response.into();
+        String::from_utf8_lossy(&buf).to_string()
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::io::Cursor;
+
     #[test]
     fn test_http_response_from_str() {
         use super::HttpStateCode;
@@ -125,4 +370,95 @@ mod test {
             "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 13\r\n\r\n<html></html>"
         );
     }
+
+    #[test]
+    fn test_binary_body() {
+        use super::HttpStateCode;
+        let png = vec![0x89, b'P', b'N', b'G', 0x00, 0xff];
+        let mut response = super::HttpResponse::new();
+        response.binary(png.clone(), "image/png", HttpStateCode::StatusOK);
+        let buf: Vec<u8> = response.into();
+        let mut expected =
+            b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 6\r\n\r\n".to_vec();
+        expected.extend(png);
+        assert_eq!(buf, expected);
+
+        let mut response = super::HttpResponse::new();
+        response.headers.clear();
+        response.write_str("你好");
+        let response_str: String = response.into();
+        assert!(response_str.ends_with("Content-Length: 6\r\n\r\n你好"));
+    }
+
+    #[test]
+    fn test_streaming_body() {
+        let mut response = super::HttpResponse::new();
+        response.headers.clear();
+        response.write_chunks(vec![b"hello ".to_vec(), Vec::new(), b"world".to_vec()]);
+        let response_str: String = response.into();
+        assert_eq!(
+            response_str,
+            "HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n"
+        );
+
+        let mut response = super::HttpResponse::new();
+        response.headers.clear();
+        response.write_reader(Cursor::new("abcdef"), Some(3));
+        let response_str: String = response.into();
+        assert_eq!(
+            response_str,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 3\r\n\r\nabc"
+        );
+
+        let mut response = super::HttpResponse::new();
+        response.headers.clear();
+        response.write_reader(Cursor::new("abcdef"), None);
+        let mut buf = Vec::new();
+        response.send(&mut buf, false, true).unwrap();
+        assert_eq!(buf, b"HTTP/1.1 404 Not Found\r\n\r\nabcdef");
+
+        let mut response = super::HttpResponse::new();
+        response.headers.clear();
+        response.write_str("hello");
+        let mut buf = Vec::new();
+        response.send(&mut buf, true, false).unwrap();
+        assert_eq!(buf, b"HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\n\r\n");
+
+        let mut response = super::HttpResponse::new();
+        response.headers.clear();
+        response.version = crate::Version::V2_0;
+        let response_str: String = response.into();
+        assert!(response_str.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_no_content_length() {
+        use super::HttpStateCode;
+
+        for (code, status_line) in [
+            (HttpStateCode::StatusNoContent, "HTTP/1.1 204 No Content"),
+            (
+                HttpStateCode::StatusNotModified,
+                "HTTP/1.1 304 Not Modified",
+            ),
+        ] {
+            let mut response = super::HttpResponse::new();
+            response.headers.clear();
+            response.set_http_state_code(code).write_str("ignored");
+            let response_str: String = response.into();
+            assert_eq!(response_str, format!("{}\r\n\r\n", status_line));
+        }
+
+        // 304的Content-Length只在显式设置时发送
+        let mut response = super::HttpResponse::new();
+        response.headers.clear();
+        response
+            .set_http_state_code(HttpStateCode::StatusNotModified)
+            .insert_header("Content-Length", "42");
+        let response_str: String = response.into();
+        assert_eq!(
+            response_str,
+            "HTTP/1.1 304 Not Modified\r\nContent-Length: 42\r\n\r\n"
+        );
+    }
 }
@@ -1,11 +1,22 @@
-use std::{collections::HashMap, fmt::Debug};
-
-use crate::{Handler, Method};
-
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    sync::Arc,
+};
+
+use crate::{
+    request::percent_decode, websocket, Handler, HttpRequest, HttpResponse, HttpStateCode, Method,
+    Middleware, Next, WebSocket, WebSocketHandler,
+};
+
+#[derive(Clone)]
 pub struct RouterHandler {
     pub method: Method,
     pub path: String,
     pub handler: Handler,
+    // 所属子路由上注册的中间件，由外到内排列
+    pub(crate) middleware: Vec<Arc<dyn Middleware>>,
 }
 
 impl Debug for RouterHandler {
@@ -19,32 +30,70 @@ impl Debug for RouterHandler {
 }
 
 impl RouterHandler {
-    pub fn new(method: Method, path: &str, handler: Handler) -> Self {
+    pub fn new<F>(method: Method, path: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
+    {
         RouterHandler {
             method,
             path: path.to_string(),
-            handler,
+            handler: Arc::new(handler),
+            middleware: Vec::new(),
+        }
+    }
+}
+
+/// 查找路由失败的原因
+#[derive(Debug, PartialEq)]
+pub enum RouteError {
+    /// 没有匹配的路径
+    NotFound(String),
+    /// 路径存在，但没有注册该method，附带该路径已注册的method
+    MethodNotAllowed(Vec<Method>),
+    /// 任何路由都没有注册该扩展method
+    NotImplemented(Method),
+}
+
+impl RouteError {
+    /// 返回该错误对应的响应状态码
+    pub fn state_code(&self) -> HttpStateCode {
+        match self {
+            RouteError::NotFound(_) => HttpStateCode::StatusNotFound,
+            RouteError::MethodNotAllowed(_) => HttpStateCode::StatusMethodNotAllowed,
+            RouteError::NotImplemented(_) => HttpStateCode::StatusNotImplemented,
+        }
+    }
+}
+
+impl Display for RouteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::NotFound(path) => write!(f, "no route for path {}", path),
+            RouteError::MethodNotAllowed(methods) => {
+                let methods: Vec<String> = methods.iter().map(|m| m.to_string()).collect();
+                write!(f, "method not allowed, allowed: {}", methods.join(", "))
+            }
+            RouteError::NotImplemented(method) => write!(f, "method {} not implemented", method),
         }
     }
 }
 
-#[derive(Default)]
+impl Error for RouteError {}
+
+#[derive(Default, Clone)]
 pub struct Router {
     // 实现 group
     group: HashMap<String, Router>,
-    get: HashMap<String, Box<RouterHandler>>,
-    post: HashMap<String, Box<RouterHandler>>,
-    put: HashMap<String, Box<RouterHandler>>,
-    delete: HashMap<String, Box<RouterHandler>>,
+    handlers: BTreeMap<Method, RouterHandler>,
+    middleware: Vec<Arc<dyn Middleware>>,
 }
 
 impl Debug for Router {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "get: {:?} \n post: {:?} \n put: {:?} \n delete: {:?} \n",
-            self.get, self.post, self.put, self.delete
-        )
+        for h in self.handlers.values() {
+            writeln!(f, "{:?}", h)?;
+        }
+        Ok(())
     }
 }
 
@@ -60,22 +109,35 @@ impl Router {
     /// ```
     /// use httpx::{
     ///     Method,
-    ///     HttpResuest,
+    ///     HttpRequest,
     ///     HttpResponse,
     ///     Router, RouterHandler,
     ///     HttpServer,
     /// };
+    /// use std::sync::{
+    ///     atomic::{AtomicUsize, Ordering},
+    ///     Arc,
+    /// };
     /// let mut router = Router::new();
     /// router.get("/", |_r, w| {
     ///     w.write_str("hello world");
     /// });
     /// router.get("/hi", route_fn);
-    /// fn route_fn(_r: &HttpResuest, w: &mut HttpResponse) {
+    /// // 2:闭包可捕获共享状态
+    /// let visits = Arc::new(AtomicUsize::new(0));
+    /// router.get("/visits", move |_r, w| {
+    ///     let n = visits.fetch_add(1, Ordering::SeqCst) + 1;
+    ///     w.write_str(&n.to_string());
+    /// });
+    /// fn route_fn(_r: &HttpRequest, w: &mut HttpResponse) {
     ///     w.insert_header("Content-Type", "text/html;charset=utf-8");
     ///     w.write_str("你好Rust");
     /// }
     /// ```
-    pub fn get(&mut self, path: &str, handler: Handler) -> &Self {
+    pub fn get<F>(&mut self, path: &str, handler: F) -> &Self
+    where
+        F: Fn(&HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
+    {
         let h = RouterHandler::new(Method::GET, path, handler);
         self.insert(h);
         self
@@ -87,7 +149,7 @@ impl Router {
     /// ```
     /// use httpx::{
     ///     Method,
-    ///     HttpResuest,
+    ///     HttpRequest,
     ///     HttpResponse,
     ///     Router, RouterHandler,
     ///     HttpServer,
@@ -97,12 +159,15 @@ impl Router {
     ///     w.write_str("hello world");
     /// });
     /// router.post("/hi", route_fn);
-    /// fn route_fn(_r: &HttpResuest, w: &mut HttpResponse) {
+    /// fn route_fn(_r: &HttpRequest, w: &mut HttpResponse) {
     ///     w.insert_header("Content-Type", "text/html;charset=utf-8");
     ///     w.write_str("你好Rust");
     /// }
     /// ```
-    pub fn post(&mut self, path: &str, handler: Handler) -> &Self {
+    pub fn post<F>(&mut self, path: &str, handler: F) -> &Self
+    where
+        F: Fn(&HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
+    {
         let h = RouterHandler::new(Method::POST, path, handler);
         self.insert(h);
         self
@@ -114,7 +179,7 @@ impl Router {
     /// ```
     /// use httpx::{
     ///     Method,
-    ///     HttpResuest,
+    ///     HttpRequest,
     ///     HttpResponse,
     ///     Router, RouterHandler,
     ///     HttpServer,
@@ -124,12 +189,15 @@ impl Router {
     ///     w.write_str("hello world");
     /// });
     /// router.put("/hi", route_fn);
-    /// fn route_fn(_r: &HttpResuest, w: &mut HttpResponse) {
+    /// fn route_fn(_r: &HttpRequest, w: &mut HttpResponse) {
     ///     w.insert_header("Content-Type", "text/html;charset=utf-8");
     ///     w.write_str("你好Rust");
     /// }
     /// ```
-    pub fn put(&mut self, path: &str, handler: Handler) -> &Self {
+    pub fn put<F>(&mut self, path: &str, handler: F) -> &Self
+    where
+        F: Fn(&HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
+    {
         let h = RouterHandler::new(Method::PUT, path, handler);
         self.insert(h);
         self
@@ -141,7 +209,7 @@ impl Router {
     /// ```
     /// use httpx::{
     ///     Method,
-    ///     HttpResuest,
+    ///     HttpRequest,
     ///     HttpResponse,
     ///     Router, RouterHandler,
     ///     HttpServer,
@@ -151,146 +219,304 @@ impl Router {
     ///     w.write_str("hello world");
     /// });
     /// router.delete("/hi", route_fn);
-    /// fn route_fn(_r: &HttpResuest, w: &mut HttpResponse) {
+    /// fn route_fn(_r: &HttpRequest, w: &mut HttpResponse) {
     ///     w.insert_header("Content-Type", "text/html;charset=utf-8");
     ///     w.write_str("你好Rust");
     /// }
     /// ```
-    pub fn delete(&mut self, path: &str, handler: Handler) -> &Self {
+    pub fn delete<F>(&mut self, path: &str, handler: F) -> &Self
+    where
+        F: Fn(&HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
+    {
         let h = RouterHandler::new(Method::DELETE, path, handler);
         self.insert(h);
         self
     }
 
-    fn insert(&mut self, h: RouterHandler) {
-        let mut current = self;
-        for part in h.path.split('/') {
-            if part.is_empty() {
-                continue;
-            }
+    /// 注册PATCH方法的route
+    /// ```
+    /// use httpx::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.patch("/users/:id", |r, w| {
+    ///     w.write_str(r.param("id").unwrap_or_default());
+    /// });
+    /// ```
+    pub fn patch<F>(&mut self, path: &str, handler: F) -> &Self
+    where
+        F: Fn(&HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
+    {
+        let h = RouterHandler::new(Method::PATCH, path, handler);
+        self.insert(h);
+        self
+    }
 
-            current = current
-                .group
-                .entry(part.to_string())
-                .or_insert_with(Router::new);
+    /// 为多个method注册同一个处理函数，也可用于注册扩展method
+    /// ```
+    /// use httpx::{Method, Router};
+    ///
+    /// let mut router = Router::new();
+    /// router.route(&[Method::GET, Method::POST], "/form", |r, w| {
+    ///     w.write_str(&r.get_method().to_string());
+    /// });
+    /// router.route(&[Method::from("PROPFIND")], "/dav/*path", |_r, w| {
+    ///     w.write_str("props");
+    /// });
+    /// ```
+    pub fn route<F>(&mut self, methods: &[Method], path: &str, handler: F) -> &Self
+    where
+        F: Fn(&HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
+    {
+        let handler: Handler = Arc::new(handler);
+        for method in methods {
+            let h = RouterHandler {
+                method: method.clone(),
+                path: path.to_string(),
+                handler: handler.clone(),
+                middleware: Vec::new(),
+            };
+            self.insert(h);
         }
+        self
+    }
 
-        let k = h.path.clone();
-        let handler = Box::new(RouterHandler::new(h.method, &h.path, h.handler));
-
-        match handler.method {
-            Method::GET => current.get.insert(k, handler),
-            Method::POST => current.post.insert(k, handler),
-            Method::PUT => current.put.insert(k, handler),
-            Method::DELETE => current.delete.insert(k, handler),
-            // Method::PATCH => todo!(),
-            // Method::OPTIONS => todo!(),
-            // Method::HEAD => todo!(),
-        };
-        // current.get = Some(handler);
+    /// 为全部标准method注册同一个处理函数，见`Method::STANDARD`
+    pub fn any<F>(&mut self, path: &str, handler: F) -> &Self
+    where
+        F: Fn(&HttpRequest, &mut HttpResponse) + Send + Sync + 'static,
+    {
+        self.route(&Method::STANDARD, path, handler)
     }
 
-    pub fn get_handler<'a>(&'a self, method: Method, path: &'a str) -> Result<&RouterHandler, String> {
-        let mut current = self;
-        let mut params = HashMap::new();
-        let mut _path = path.clone().to_string();
+    /// 注册WebSocket路由，按RFC 6455完成握手后，在线程池之外的独立线程中调用handler
+    ///
+    /// 注册为GET路由，中间件和路径参数与普通路由相同。握手请求不合法时返回400，
+    /// 缺少`Upgrade: websocket`或版本不是13时返回426。handler返回时关闭连接，
+    /// 单条消息的最大长度默认与`HttpServer::set_max_body_size`相同
+    /// ```
+    /// use httpx::{Message, Router};
+    ///
+    /// let mut router = Router::new();
+    /// router.websocket("/chat/:room", |r, mut ws| {
+    ///     let room = r.param("room").unwrap_or_default().to_string();
+    ///     while let Ok(message) = ws.recv() {
+    ///         if let Message::Text(text) = message {
+    ///             let _ = ws.send(Message::Text(format!("{}: {}", room, text)));
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn websocket<F>(&mut self, path: &str, handler: F) -> &Self
+    where
+        F: Fn(&HttpRequest, WebSocket) + Send + Sync + 'static,
+    {
+        let handler: WebSocketHandler = Arc::new(handler);
+        let h = RouterHandler::new(Method::GET, path, move |r, w| {
+            websocket::accept(r, w, &handler)
+        });
+        self.insert(h);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// 路由不合法或与已注册的路由冲突时panic，见`try_add_route`
+    fn insert(&mut self, h: RouterHandler) {
+        if let Err(e) = self.try_add_route(h) {
+            panic!("{}", e);
+        }
+    }
 
-        for (i, part) in path.split('/').enumerate() {
-            if part.is_empty() {
-                continue;
+    /// 将route注册进Router中，路由不合法或与已注册的路由冲突时返回错误：
+    ///
+    /// - `*name`不是路由的最后一段
+    /// - `:`或`*`之后的参数名为空
+    /// - 同一位置已存在参数名不同的`:name`或`*name`，如`/users/:id`与`/users/:name`
+    /// - 同一method和path已注册过，`/users`与`/users/`视为相同的path
+    pub fn try_add_route(&mut self, h: RouterHandler) -> Result<(), String> {
+        let parts: Vec<&str> = h.path.split('/').filter(|p| !p.is_empty()).collect();
+        for (i, part) in parts.iter().enumerate() {
+            if *part == ":" {
+                return Err(format!("empty parameter name in route {}", h.path));
+            }
+            if part.len() > 1 && part.starts_with('*') && i != parts.len() - 1 {
+                return Err(format!(
+                    "catch-all segment {} must be the last segment of route {}",
+                    part, h.path
+                ));
             }
+        }
 
-            if let Some(node) = current.group.get(part) {
-                current = node;
-            } else {
-                // Check for dynamic route
-                for (key, node) in current.group.iter() {
-                    // println!("key: {}", key);
-                    if key.starts_with(':') {
-                        let mut list: Vec<&str> = _path.split("/").collect();
-                        let i = list.partition_point(|v| v.ne(&part));
-                        let s = String::from(key);
-                        list[i] = &s;
-                        _path = list.join("/");
-
-                        // println!("{}", _path);
-
-                        params.insert(&key[1..], part);
-                        current = node;
-                        break;
-                    }
+        // 先检查冲突，确保出错时不会留下新建的节点
+        let mut node = Some(&*self);
+        for part in parts.iter() {
+            let current = match node {
+                Some(current) => current,
+                None => break,
+            };
+            let kind = |key: &str| match key.chars().next() {
+                Some(':') => Some(':'),
+                Some('*') if key.len() > 1 => Some('*'),
+                _ => None,
+            };
+            if let Some(k) = kind(part) {
+                if let Some(other) = current
+                    .group
+                    .keys()
+                    .find(|key| kind(key) == Some(k) && key.as_str() != *part)
+                {
+                    return Err(format!(
+                        "segment {} of route {} conflicts with existing segment {}",
+                        part, h.path, other
+                    ));
                 }
             }
+            node = current.group.get(*part);
+        }
+        if node.is_some_and(|n| n.handlers.contains_key(&h.method)) {
+            return Err(format!("duplicate route {} {}", h.method, h.path));
+        }
+
+        let mut current = self;
+        for part in parts {
+            current = current.group.entry(part.to_string()).or_default();
+        }
+
+        current.handlers.insert(h.method.clone(), h);
+        Ok(())
+    }
+
+    // 没有显式注册HEAD时，HEAD请求使用GET的处理函数
+    fn handler(&self, method: &Method) -> Option<&RouterHandler> {
+        match (self.handlers.get(method), method) {
+            (None, Method::HEAD) => self.handlers.get(&Method::GET),
+            (h, _) => h,
+        }
+    }
+
+    /// 查找method和path对应的处理函数，同时返回匹配到的路径参数
+    ///
+    /// 路由段支持以下几种形式，同一位置按 字面量 > `:name` > `*` > `*name` 的固定优先级匹配，
+    /// 优先级高的分支匹配失败时会回退尝试优先级低的分支，匹配结果与注册顺序无关：
+    ///
+    /// - `users`：字面量，必须完全相同
+    /// - `:name`：匹配任意一段，值保存为参数`name`
+    /// - `*`：匹配任意一段，不保存参数
+    /// - `*name`：匹配剩余的全部路径(可以为空)，值保存为参数`name`，只能作为最后一段
+    pub fn get_handler(
+        &self,
+        method: Method,
+        path: &str,
+    ) -> Result<(&RouterHandler, HashMap<String, String>), RouteError> {
+        let segments: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        let mut params = Vec::new();
+        let node = self.find(&segments, &mut params, &|node| {
+            node.handler(&method).is_some()
+        });
+        if let Some(h) = node.and_then(|node| node.handler(&method)) {
+            return Ok((h, params.into_iter().collect()));
+        }
+
+        let mut methods = BTreeSet::new();
+        self.collect_methods(&mut methods);
+        if matches!(method, Method::Extension(_)) && !methods.contains(&method) {
+            return Err(RouteError::NotImplemented(method));
+        }
+        match self.allowed_methods(path) {
+            methods if methods.is_empty() => Err(RouteError::NotFound(path.to_string())),
+            methods => Err(RouteError::MethodNotAllowed(methods)),
+        }
+    }
+
+    /// 返回path可以匹配到的全部method，按`Method`的声明顺序排列
+    ///
+    /// 可以匹配GET的path同时允许HEAD
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let segments: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+        let mut methods = BTreeSet::new();
+        self.collect_methods(&mut methods);
+        if methods.contains(&Method::GET) {
+            methods.insert(Method::HEAD);
+        }
+        methods
+            .into_iter()
+            .filter(|method| {
+                self.find(&segments, &mut Vec::new(), &|node| {
+                    node.handler(method).is_some()
+                })
+                .is_some()
+            })
+            .collect()
+    }
+
+    // 收集全部已注册的method
+    fn collect_methods(&self, methods: &mut BTreeSet<Method>) {
+        methods.extend(self.handlers.keys().cloned());
+        for child in self.group.values() {
+            child.collect_methods(methods);
+        }
+    }
 
-            if (i == path.split('/').count() - 1)
-                && current.get.is_empty()
-                && current.post.is_empty()
-                && current.put.is_empty()
-                && current.delete.is_empty()
+    // 深度优先按优先级匹配，accept判断节点是否可以作为匹配结果
+    fn find<'a>(
+        &'a self,
+        segments: &[&str],
+        params: &mut Vec<(String, String)>,
+        accept: &dyn Fn(&Router) -> bool,
+    ) -> Option<&'a Router> {
+        let (part, rest) = match segments.split_first() {
+            Some(split) => split,
+            None if accept(self) => return Some(self),
+            None => return self.find_catch_all(segments, params, accept),
+        };
+
+        if !part.starts_with(':') && !part.starts_with('*') {
+            if let Some(node) = self
+                .group
+                .get(*part)
+                .and_then(|n| n.find(rest, params, accept))
             {
-                return Err(format!("missing {} handler for path {}", method, path));
+                return Some(node);
             }
+        }
 
-            // if i == path.split('/').count() - 1 {
-            //     match method {
-            //         Method::GET => {
-            //             if current.get.is_empty() {
-            //                 return  None
-            //             }
-            //         },
-            //         Method::POST => {
-            //             if current.post.is_empty() {
-            //                 return  None
-            //             }
-            //         },
-            //         Method::PUT => {
-            //             if current.put.is_empty() {
-            //                 return  None
-            //             }
-            //         },
-            //         Method::DELETE => {
-            //             if current.delete.is_empty() {
-            //                 return  None
-            //             }
-            //         },
-            //     }
-            // }
-
-            // println!("{}, {}, {}, {}", current.get.is_none(), current.post.is_none(), current.put.is_none(), current.delete.is_none());
-
-            // If there's no dynamic route and no match, return None
-            // if i == path.split('/').count() - 1 && current.get.is_none() {
-            //     return None;
-            // }
+        // 注册时已保证同一位置最多只有一个`:name`
+        if let Some((key, child)) = self.group.iter().find(|(k, _)| k.starts_with(':')) {
+            params.push((key[1..].to_string(), percent_decode(part)));
+            if let Some(node) = child.find(rest, params, accept) {
+                return Some(node);
+            }
+            params.pop();
         }
 
-        match method {
-            Method::GET => match current.get.get(&_path) {
-                Some(h) => Ok(&h),
-                None => return Err(format!("missing get handler for path {}", path)),
-            },
-            Method::POST => match current.post.get(&_path) {
-                Some(h) => Ok(&h),
-                None => return Err(format!("missing post handler for path {}", path)),
-            },
-            Method::PUT => match current.put.get(&_path) {
-                Some(h) => Ok(&h),
-                None => return Err(format!("missing put handler for path {}", path)),
-            },
-            Method::DELETE => match current.delete.get(&_path) {
-                Some(h) => Ok(&h),
-                None => return Err(format!("missing delete handler for path {}", path)),
-            },
-            // Method::PATCH => todo!(),
-            // Method::OPTIONS => todo!(),
-            // Method::HEAD => todo!(),
+        if let Some(node) = self
+            .group
+            .get("*")
+            .and_then(|n| n.find(rest, params, accept))
+        {
+            return Some(node);
         }
 
-        // if let Some(handler) = current.get {
-        //     Some(handler)
-        // } else {
-        //     None
-        // }
+        self.find_catch_all(segments, params, accept)
+    }
+
+    fn find_catch_all<'a>(
+        &'a self,
+        segments: &[&str],
+        params: &mut Vec<(String, String)>,
+        accept: &dyn Fn(&Router) -> bool,
+    ) -> Option<&'a Router> {
+        // 注册时已保证同一位置最多只有一个`*name`
+        let (key, child) = self
+            .group
+            .iter()
+            .find(|(k, _)| k.len() > 1 && k.starts_with('*'))?;
+        if !accept(child) {
+            return None;
+        }
+        let rest: Vec<String> = segments.iter().map(|p| percent_decode(p)).collect();
+        params.push((key[1..].to_string(), rest.join("/")));
+        Some(child)
     }
 
     /// 将route注册进Router中
@@ -299,7 +525,7 @@ impl Router {
     /// ```
     /// use httpx::{
     ///     Method,
-    ///     HttpResuest,
+    ///     HttpRequest,
     ///     HttpResponse,
     ///     Router, RouterHandler,
     ///     HttpServer,
@@ -309,30 +535,116 @@ impl Router {
     ///     w.write_str("hello world");
     /// }));
     /// router.add_route(RouterHandler::new(Method::GET, "/hi", route_fn));
-    /// fn route_fn(_r: &HttpResuest, w: &mut HttpResponse) {
+    /// fn route_fn(_r: &HttpRequest, w: &mut HttpResponse) {
     ///     w.insert_header("Content-Type", "text/html;charset=utf-8");
     ///     w.write_str("你好Rust");
     /// }
     /// ```
+    ///
+    /// # Panics
+    ///
+    /// 路由不合法或与已注册的路由冲突时panic，不希望panic时使用`try_add_route`
     pub fn add_route(&mut self, handler: RouterHandler) {
-        match handler.method {
-            Method::GET => self.get(&handler.path, handler.handler),
-            Method::POST => self.post(&handler.path, handler.handler),
-            Method::PUT => self.put(&handler.path, handler.handler),
-            Method::DELETE => self.delete(&handler.path, handler.handler),
-            // Method::PATCH => todo!(),
-            // Method::OPTIONS => todo!(),
-            // Method::HEAD => todo!(),
-            // Method::NoSupport => &Router::from(Router::new()),
-        };
+        self.insert(handler);
+    }
+
+    /// 将子路由的全部route挂载到prefix下
+    ///
+    /// Router可以clone，同一个子路由挂载到多个prefix下时共享相同的处理函数
+    /// ```
+    /// use httpx::Router;
+    ///
+    /// let mut users = Router::new();
+    /// users.get("/", |_r, w| {
+    ///     w.write_str("user list");
+    /// });
+    /// users.get("/:id", |r, w| {
+    ///     w.write_str(r.param("id").unwrap_or_default());
+    /// });
+    ///
+    /// let mut router = Router::new();
+    /// router.nest("/api/v1/users", users.clone());
+    /// router.nest("/api/latest/users", users);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// 子路由中的route与已注册的路由冲突时panic
+    pub fn nest(&mut self, prefix: &str, router: Router) -> &Self {
+        let prefix = prefix.trim_matches('/');
+        let middleware = router.middleware.clone();
+        for mut h in router.into_routes() {
+            h.middleware.splice(0..0, middleware.iter().cloned());
+            let path = h.path.trim_start_matches('/');
+            h.path = match (prefix.is_empty(), path.is_empty()) {
+                (true, _) => format!("/{}", path),
+                (false, true) => format!("/{}", prefix),
+                (false, false) => format!("/{}/{}", prefix, path),
+            };
+            self.insert(h);
+        }
+        self
     }
 
+    /// 在闭包中构建子路由，并挂载到prefix下
+    /// ```
+    /// use httpx::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.scope("/admin", |admin| {
+    ///     admin.get("/stats", |_r, w| {
+    ///         w.write_str("stats");
+    ///     });
+    /// });
+    /// ```
+    pub fn scope<F>(&mut self, prefix: &str, f: F) -> &Self
+    where
+        F: FnOnce(&mut Router),
+    {
+        let mut router = Router::new();
+        f(&mut router);
+        self.nest(prefix, router)
+    }
+
+    /// 注册中间件，只对该Router中的route生效，被`nest`到其他Router下之后依然生效
+    ///
+    /// 执行顺序见`Middleware`
+    pub fn middleware<M: Middleware + 'static>(&mut self, middleware: M) -> &Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    // 依次执行Router上的中间件、route所属子路由上的中间件和处理函数
+    pub(crate) fn call(
+        &self,
+        h: &RouterHandler,
+        request: &mut HttpRequest,
+        response: &mut HttpResponse,
+    ) {
+        let chain: Vec<Arc<dyn Middleware>> = self
+            .middleware
+            .iter()
+            .chain(h.middleware.iter())
+            .cloned()
+            .collect();
+        let endpoint =
+            |request: &mut HttpRequest, response: &mut HttpResponse| (h.handler)(request, response);
+        Next::new(&chain, &endpoint).run(request, response);
+    }
+
+    // 取出全部已注册的route
+    fn into_routes(self) -> Vec<RouterHandler> {
+        let mut routes: Vec<RouterHandler> = self.handlers.into_values().collect();
+        for (_, child) in self.group {
+            routes.extend(child.into_routes());
+        }
+        routes
+    }
 }
 
 impl From<Router> for String {
     fn from(_r: Router) -> Self {
-        let body_str = format!("NoSupport \r\n");
-        return body_str;
+        "NoSupport \r\n".to_string()
     }
 }
 
@@ -356,7 +668,7 @@ impl From<Router> for String {
 //     /// ```
 //     /// use http::{
 //     ///     http_method::method::Method,
-//     ///     http_request::request::HttpResuest,
+//     ///     http_request::request::HttpRequest,
 //     ///     http_response::response::HttpResponse,
 //     ///     http_router::{router::Router, router_handler::RouterHandler},
 //     ///     http_server::server::HttpServer,
@@ -366,7 +678,7 @@ impl From<Router> for String {
 //     ///     w.write_str("hello world");
 //     /// }));
 //     /// router.add_route(RouterHandler::new(Method::GET, "/hi", route_fn));
-//     /// fn route_fn(_r: &HttpResuest, w: &mut HttpResponse) {
+//     /// fn route_fn(_r: &HttpRequest, w: &mut HttpResponse) {
 //     ///     w.insert_header("Content-Type", "text/html;charset=utf-8");
 //     ///     w.write_str("你好Rust");
 //     /// }
@@ -399,3 +711,308 @@ impl From<Router> for String {
 //         None
 //     }
 // }
+
+#[cfg(test)]
+mod test_router {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::{RouteError, Router, RouterHandler};
+    use crate::{HttpRequest, HttpResponse, Method, Middleware, Next};
+
+    #[test]
+    fn test_closure_handler() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut router = Router::new();
+        let c = counter.clone();
+        router.post("/count", move |_r, w| {
+            let n = c.fetch_add(1, Ordering::SeqCst) + 1;
+            w.write_str(&n.to_string());
+        });
+
+        let request = HttpRequest::default();
+        for _ in 0..2 {
+            let (handler, _) = router.get_handler(Method::POST, "/count").unwrap();
+            (handler.handler)(&request, &mut HttpResponse::new());
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let mut router = Router::new();
+        router.get("/static/*path", |_r, _w| {});
+        router.get("/static/index.html", |_r, _w| {});
+        router.get("/static/:dir/meta", |_r, _w| {});
+        router.get("/avatar/*/small", |_r, _w| {});
+        router.get("/*fallback", |_r, _w| {});
+
+        let matched = |path: &str| {
+            let (h, params) = router.get_handler(Method::GET, path).unwrap();
+            let mut params: Vec<(String, String)> = params.into_iter().collect();
+            params.sort();
+            (h.path.clone(), params)
+        };
+        let param = |k: &str, v: &str| (k.to_string(), v.to_string());
+
+        assert_eq!(
+            matched("/static/index.html"),
+            ("/static/index.html".to_string(), vec![])
+        );
+        assert_eq!(
+            matched("/static/css/meta"),
+            ("/static/:dir/meta".to_string(), vec![param("dir", "css")])
+        );
+        // :dir 分支匹配失败后回退到 *path
+        assert_eq!(
+            matched("/static/css/site.css"),
+            (
+                "/static/*path".to_string(),
+                vec![param("path", "css/site.css")]
+            )
+        );
+        assert_eq!(
+            matched("/static"),
+            ("/static/*path".to_string(), vec![param("path", "")])
+        );
+        assert_eq!(
+            matched("/avatar/1/small"),
+            ("/avatar/*/small".to_string(), vec![])
+        );
+        assert_eq!(
+            matched("/avatar/1/large"),
+            (
+                "/*fallback".to_string(),
+                vec![param("fallback", "avatar/1/large")]
+            )
+        );
+        assert_eq!(
+            matched("/"),
+            ("/*fallback".to_string(), vec![param("fallback", "")])
+        );
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let mut router = Router::new();
+        router.get("/users/:id", |_r, _w| {});
+        router.get("/files/*path", |_r, _w| {});
+        let route = |path: &str| RouterHandler::new(Method::GET, path, |_r, _w| {});
+
+        assert!(router.try_add_route(route("/users/:name/posts")).is_err());
+        assert!(router.try_add_route(route("/files/*rest")).is_err());
+        assert!(router.try_add_route(route("/users/:id/")).is_err());
+        assert!(router.try_add_route(route("/users/:/x")).is_err());
+        // 冲突时不会留下新建的节点
+        assert!(router.get_handler(Method::GET, "/users/1/posts").is_err());
+
+        assert!(router.try_add_route(route("/users/:id/posts")).is_ok());
+        assert!(router.try_add_route(route("/files/*")).is_ok());
+        assert!(router
+            .try_add_route(RouterHandler::new(Method::POST, "/users/:id", |_r, _w| {}))
+            .is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate route GET /")]
+    fn test_duplicate_route() {
+        let mut router = Router::new();
+        router.get("/", |_r, _w| {});
+        router.get("/", |_r, _w| {});
+    }
+
+    #[test]
+    #[should_panic(expected = "must be the last segment")]
+    fn test_catch_all_not_last() {
+        let mut router = Router::new();
+        router.get("/files/*path/edit", |_r, _w| {});
+    }
+
+    #[test]
+    fn test_path_params() {
+        let mut router = Router::new();
+        router.get("/users/:id", |_r, _w| {});
+        router.get("/users/:id/files/:name", |_r, _w| {});
+
+        let (handler, params) = router.get_handler(Method::GET, "/users/42").unwrap();
+        assert_eq!(handler.path, "/users/:id");
+        assert_eq!(params.get("id").map(String::as_str), Some("42"));
+
+        let (handler, params) = router
+            .get_handler(Method::GET, "/users/7/files/a%20b.txt")
+            .unwrap();
+        assert_eq!(handler.path, "/users/:id/files/:name");
+        assert_eq!(params.get("id").map(String::as_str), Some("7"));
+        assert_eq!(params.get("name").map(String::as_str), Some("a b.txt"));
+
+        assert!(router.get_handler(Method::GET, "/users/7/other").is_err());
+        assert!(router.get_handler(Method::POST, "/users/7").is_err());
+    }
+
+    #[test]
+    fn test_nest() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let mut users = Router::new();
+        let c = counter.clone();
+        users.get("/", move |_r, _w| {
+            c.fetch_add(1, Ordering::SeqCst);
+        });
+        users.put("/:id/", |_r, _w| {});
+
+        let mut router = Router::new();
+        router.nest("/api/v1/users/", users.clone());
+        router.nest("/latest/users", users);
+        router.scope("/", |r| {
+            r.get("/health", |_r, _w| {});
+        });
+
+        let request = HttpRequest::default();
+        for path in ["/api/v1/users", "/latest/users/"] {
+            let (h, _) = router.get_handler(Method::GET, path).unwrap();
+            (h.handler)(&request, &mut HttpResponse::new());
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 2);
+
+        let (h, params) = router.get_handler(Method::PUT, "/api/v1/users/3").unwrap();
+        assert_eq!(h.path, "/api/v1/users/:id/");
+        assert_eq!(params.get("id").map(String::as_str), Some("3"));
+        assert!(router.get_handler(Method::GET, "/health").is_ok());
+        assert!(router.get_handler(Method::GET, "/users").is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate route GET /api/health")]
+    fn test_nest_conflict() {
+        let mut router = Router::new();
+        router.get("/api/health", |_r, _w| {});
+        router.scope("/api", |api| {
+            api.get("/health", |_r, _w| {});
+        });
+    }
+
+    #[test]
+    fn test_group_middleware() {
+        fn tag(name: &'static str) -> impl Middleware {
+            move |req: &mut HttpRequest, resp: &mut HttpResponse, next: Next| {
+                let trace = req.get_header("Trace").unwrap_or_default().to_string();
+                req.insert_header("Trace", &format!("{}{}", trace, name));
+                next.run(req, resp);
+            }
+        }
+        let handler = |r: &HttpRequest, w: &mut HttpResponse| {
+            w.write_str(r.get_header("Trace").unwrap_or_default());
+        };
+
+        let mut v1 = Router::new();
+        v1.middleware(tag("v1"));
+        v1.scope("/admin", |admin| {
+            admin.middleware(tag("admin"));
+            admin.get("/", handler);
+        });
+        v1.get("/public", handler);
+
+        let mut router = Router::new();
+        router.get("/", handler);
+        router.nest("/v1", v1);
+        router.middleware(tag("root"));
+
+        let body = |path: &str| {
+            let (h, _) = router.get_handler(Method::GET, path).unwrap();
+            let mut resp = HttpResponse::new();
+            router.call(h, &mut HttpRequest::default(), &mut resp);
+            let buf: Vec<u8> = resp.into();
+            String::from_utf8(buf).unwrap()
+        };
+        assert!(body("/").ends_with("\r\n\r\nroot"));
+        assert!(body("/v1/public").ends_with("\r\n\r\nrootv1"));
+        assert!(body("/v1/admin").ends_with("\r\n\r\nrootv1admin"));
+    }
+
+    #[test]
+    fn test_route_error() {
+        let mut router = Router::new();
+        router.get("/users/:id", |_r, _w| {});
+        router.put("/users/:id", |_r, _w| {});
+        router.post("/*any", |_r, _w| {});
+
+        assert_eq!(
+            router.get_handler(Method::DELETE, "/users/1").unwrap_err(),
+            RouteError::MethodNotAllowed(vec![
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::HEAD
+            ])
+        );
+        assert_eq!(
+            router.get_handler(Method::GET, "/other").unwrap_err(),
+            RouteError::MethodNotAllowed(vec![Method::POST])
+        );
+
+        let router = Router::new();
+        assert_eq!(
+            router.get_handler(Method::GET, "/").unwrap_err(),
+            RouteError::NotFound("/".to_string())
+        );
+    }
+
+    #[test]
+    fn test_head_falls_back_to_get() {
+        let mut router = Router::new();
+        router.get("/users/:id", |_r, _w| {});
+        router.get("/files", |_r, _w| {});
+        router.route(&[Method::HEAD], "/files", |_r, _w| {});
+
+        let (h, params) = router.get_handler(Method::HEAD, "/users/1").unwrap();
+        assert_eq!(h.method, Method::GET);
+        assert_eq!(params["id"], "1");
+        let (h, _) = router.get_handler(Method::HEAD, "/files").unwrap();
+        assert_eq!(h.method, Method::HEAD);
+        assert_eq!(
+            router.allowed_methods("/users/1"),
+            [Method::GET, Method::HEAD]
+        );
+    }
+
+    #[test]
+    fn test_more_methods() {
+        let mut router = Router::new();
+        router.get("/users/:id", |_r, w| {
+            w.write_str("get");
+        });
+        router.patch("/users/:id", |_r, w| {
+            w.write_str("patch");
+        });
+        router.route(
+            &[Method::from("PROPFIND"), Method::PUT],
+            "/dav/*path",
+            |_r, _w| {},
+        );
+        router.any("/echo", |_r, _w| {});
+
+        let (h, _) = router.get_handler(Method::PATCH, "/users/1").unwrap();
+        assert_eq!(h.method, Method::PATCH);
+        assert!(router
+            .get_handler(Method::from("PROPFIND"), "/dav/a/b")
+            .is_ok());
+        assert_eq!(
+            router.allowed_methods("/dav/a"),
+            [Method::PUT, Method::from("PROPFIND")]
+        );
+        assert_eq!(router.allowed_methods("/echo"), Method::STANDARD);
+        assert_eq!(
+            router
+                .get_handler(Method::from("PROPFIND"), "/users/1")
+                .unwrap_err(),
+            RouteError::MethodNotAllowed(vec![Method::GET, Method::PATCH, Method::HEAD])
+        );
+        assert_eq!(
+            router
+                .get_handler(Method::from("PURGE"), "/users/1")
+                .unwrap_err(),
+            RouteError::NotImplemented(Method::from("PURGE"))
+        );
+    }
+}
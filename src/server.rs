@@ -1,23 +1,166 @@
 use std::{
-    io::{Read, Write},
-    net::{TcpListener, TcpStream},
-    sync::Arc,
+    collections::HashMap,
+    error::Error,
+    fmt::{Display, Formatter},
+    io::{self, Cursor, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener},
+    panic,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
 };
 
-use num_cpus;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
 
-use crate::{ThreadPool, HttpRequest, HttpResponse, HttpStateCode, Router};
+#[cfg(feature = "tls")]
+use rustls::ServerConfig;
 
-pub(crate) trait Executor {
-    fn executor(&self, stream: TcpStream);
+#[cfg(feature = "tls")]
+use crate::tls::TlsSettings;
+use crate::{
+    h2::{self, Connection, StreamLimit},
+    net::{Listener, LocalAddr, Stream},
+    parser::{ParseError, ParseLimits, RequestReader},
+    AppState, HttpRequest, HttpResponse, HttpStateCode, Method, Middleware, Next, RouteError,
+    Router, ShutdownHandle, ThreadPool, Version, WebSocket,
+};
+
+/// HttpServer运行时的错误
+#[derive(Debug)]
+pub enum ServerError {
+    /// 绑定监听地址失败，如端口已被占用
+    Bind { addr: String, source: io::Error },
+    /// 监听socket不可用，无法继续接受连接
+    Accept(io::Error),
+    /// 连接读写失败
+    Io(io::Error),
+    /// 请求解析失败
+    Parse(ParseError),
+    /// 证书或私钥加载失败
+    #[cfg(feature = "tls")]
+    Tls(String),
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerError::Bind { addr, source } => write!(f, "bind {} failed: {}", addr, source),
+            ServerError::Accept(e) => write!(f, "accept failed: {}", e),
+            ServerError::Io(e) => write!(f, "io error: {}", e),
+            ServerError::Parse(e) => write!(f, "parse request failed: {}", e),
+            #[cfg(feature = "tls")]
+            ServerError::Tls(msg) => write!(f, "tls config error: {}", msg),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServerError::Bind { source, .. } => Some(source),
+            ServerError::Accept(e) | ServerError::Io(e) => Some(e),
+            ServerError::Parse(e) => Some(e),
+            #[cfg(feature = "tls")]
+            ServerError::Tls(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ServerError {
+    fn from(e: io::Error) -> Self {
+        ServerError::Io(e)
+    }
 }
 
+impl From<ParseError> for ServerError {
+    fn from(e: ParseError) -> Self {
+        ServerError::Parse(e)
+    }
+}
+
+/// `HttpServer::spawn`返回的句柄，drop时不会停止server
 #[derive(Debug)]
-pub struct HttpServer {
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    shutdown: ShutdownHandle,
+    thread: JoinHandle<Result<(), ServerError>>,
+}
+
+impl ServerHandle {
+    /// 实际监听的第一个TCP地址，只监听了Unix domain socket时返回None
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs.first().copied()
+    }
+
+    /// 实际监听的全部TCP地址，顺序与添加顺序相同
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 停止server并等待后台线程退出
+    pub fn shutdown(self) -> Result<(), ServerError> {
+        self.shutdown.shutdown();
+        self.join()
+    }
+
+    /// 等待后台线程退出，返回`start`的结果
+    pub fn join(self) -> Result<(), ServerError> {
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+}
+
+// 一个监听地址，router为None时使用mount_route挂载的Router
+#[derive(Debug)]
+struct Listen {
     addr: String,
+    router: Option<Arc<Router>>,
+    // 已绑定的监听socket，start时取出
+    listener: Mutex<Option<Listener>>,
+    local_addr: Option<LocalAddr>,
+    // 由bind绑定，已按需启用TLS，start时无需再次加载证书
+    bound: bool,
+}
+
+impl Listen {
+    fn new(addr: &str, router: Option<Arc<Router>>) -> Self {
+        Listen {
+            addr: addr.to_string(),
+            router,
+            listener: Mutex::new(None),
+            local_addr: None,
+            bound: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HttpServer {
+    // 第一个为set_addr设置的地址，之后为add_addr添加的地址
+    listens: Vec<Listen>,
     router: Arc<Router>,
     pool: ThreadPool,
-    response: HttpResponse,
+    headers: HashMap<String, String>,
+    state: Arc<AppState>,
+    middleware: Arc<Vec<Arc<dyn Middleware>>>,
+    limits: ParseLimits,
+    keep_alive_timeout: Duration,
+    read_timeout: Duration,
+    max_requests: usize,
+    shutdown: ShutdownHandle,
+    shutdown_timeout: Duration,
+    http2: bool,
+    h2c: bool,
+    h2_streams: Arc<StreamLimit>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsSettings>,
 }
 
 impl HttpServer {
@@ -26,6 +169,68 @@ impl HttpServer {
         HttpServer::default()
     }
 
+    /// 使用已绑定的TcpListener创建HttpServer，如systemd socket activation传入的socket
+    ///
+    /// 此时`set_addr`设置的地址不再生效
+    pub fn from_listener(listener: TcpListener) -> Self {
+        Self::with_listener(Listener::Tcp(listener))
+    }
+
+    /// 使用已绑定的UnixListener创建HttpServer，`HttpRequest::get_remote_addr`返回`unix:`加socket路径
+    #[cfg(unix)]
+    pub fn from_unix_listener(listener: UnixListener) -> Self {
+        Self::with_listener(Listener::Unix {
+            listener,
+            _file: None,
+        })
+    }
+
+    fn with_listener(listener: Listener) -> Self {
+        let mut server = HttpServer::default();
+        let listen = &mut server.listens[0];
+        listen.local_addr = listener.local_addr().ok();
+        if let Some(addr) = &listen.local_addr {
+            listen.addr = addr.to_string();
+        }
+        listen.listener = Mutex::new(Some(listener));
+        server
+    }
+
+    /// 立即绑定全部监听地址，之后可以通过`local_addr`获取实际监听的地址(如端口为0时系统分配的端口)
+    pub fn bind(&mut self) -> Result<&mut Self, ServerError> {
+        #[cfg(feature = "tls")]
+        let tls = self.tls_config()?;
+        for listen in self.listens.iter_mut() {
+            let listener = listen.listener.get_mut().unwrap();
+            let bound = match listener.take() {
+                Some(bound) => bound,
+                None => Self::bind_addr(&listen.addr)?,
+            };
+            listen.local_addr = Some(bound.local_addr()?);
+            #[cfg(feature = "tls")]
+            let bound = bound.with_tls(tls.as_ref());
+            *listener = Some(bound);
+            listen.bound = true;
+        }
+        Ok(self)
+    }
+
+    /// 返回实际监听的第一个TCP地址，通过`from_listener`创建或调用`bind`之后可用
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs().first().copied()
+    }
+
+    /// 返回已绑定的全部TCP监听地址，不包括Unix domain socket
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listens
+            .iter()
+            .filter_map(|l| match l.local_addr {
+                Some(LocalAddr::Tcp(addr)) => Some(addr),
+                _ => None,
+            })
+            .collect()
+    }
+
     /// 设置HttpServer参数
     pub fn configure<F>(&mut self, opt: F) -> &mut Self
     where
@@ -41,122 +246,558 @@ impl HttpServer {
     }
 
     pub fn mount_header(&mut self, key: &str, value: &str) -> &mut Self {
-        self.response.insert_header(key, value);
+        self.headers.insert(key.to_string(), value.to_string());
         // header.insert("Access-Control-Allow-Origin".to_string(), "*".to_owned());
         self
     }
 
-    /// 启动Http服务
-    pub fn start(&self) {
-        let listener = TcpListener::bind(&self.addr).unwrap();
-        println!("http server start at {}", self.addr);
-        for stream in listener.incoming() {
+    /// 注册对所有请求生效的中间件，执行顺序见`Middleware`
+    pub fn middleware<M: Middleware + 'static>(&mut self, middleware: M) -> &mut Self {
+        Arc::make_mut(&mut self.middleware).push(Arc::new(middleware));
+        self
+    }
+
+    /// 返回停止server的句柄，见`ShutdownHandle`
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 启动Http服务，阻塞直到通过`ShutdownHandle`停止
+    ///
+    /// 监听地址绑定失败或监听socket不可用时返回错误，单个连接上的错误只会关闭该连接
+    pub fn start(&self) -> Result<(), ServerError> {
+        // 调用过bind时证书已经加载
+        #[cfg(feature = "tls")]
+        let tls = match self.listens.iter().all(|listen| listen.bound) {
+            true => None,
+            false => self.tls_config()?,
+        };
+        // 全部地址绑定成功后才开始接受连接
+        let mut listeners = Vec::with_capacity(self.listens.len());
+        for listen in self.listens.iter() {
+            let listener = match listen.listener.lock().unwrap().take() {
+                Some(listener) => listener,
+                None => Self::bind_addr(&listen.addr)?,
+            };
+            #[cfg(feature = "tls")]
+            let listener = match listen.bound {
+                true => listener,
+                false => listener.with_tls(tls.as_ref()),
+            };
+            let router = listen.router.clone().unwrap_or_else(|| self.router.clone());
+            listeners.push((listener, router));
+        }
+        for (listener, _) in listeners.iter() {
+            let local_addr = listener.local_addr()?;
+            println!("http server start at {}", local_addr);
+            if !self.shutdown.add_listener(local_addr) {
+                return Ok(());
+            }
+        }
+
+        // 每个地址一个accept线程，连接交给同一个线程池处理
+        let result = thread::scope(|s| {
+            let accepts: Vec<_> = listeners
+                .into_iter()
+                .map(|(listener, router)| s.spawn(move || self.accept(listener, router)))
+                .collect();
+            accepts
+                .into_iter()
+                .map(|accept| accept.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .fold(Ok(()), Result::and)
+        });
+        println!("http server shutting down");
+        self.shutdown.wait(self.shutdown_timeout);
+        result
+    }
+
+    fn accept(&self, listener: Listener, router: Arc<Router>) -> Result<(), ServerError> {
+        loop {
+            let stream = listener.accept();
+            if self.shutdown.is_shutdown() {
+                break;
+            }
             match stream {
                 Ok(stream) => {
                     // stream只对当前请求有效，故在此可转移所有权而非借用
-                    self.executor(stream);
-                }
-                Err(_e) => {
-                    println!("start err: {}", _e);
-                    continue;
+                    self.executor(stream, router.clone());
                 }
+                Err(e) => match e.kind() {
+                    // 一个地址不可用时停止整个server
+                    ErrorKind::InvalidInput | ErrorKind::Unsupported => {
+                        self.shutdown.shutdown();
+                        return Err(ServerError::Accept(e));
+                    }
+                    // 客户端在accept之前断开，不影响后续连接
+                    ErrorKind::ConnectionAborted
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::Interrupted => continue,
+                    // 文件描述符耗尽等资源不足的错误，稍后重试
+                    _ => {
+                        println!("{}", ServerError::Accept(e));
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                },
             }
         }
+        Ok(())
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_config(&self) -> Result<Option<Arc<ServerConfig>>, ServerError> {
+        self.tls
+            .as_ref()
+            .map(|tls| tls.build(self.http2))
+            .transpose()
+    }
+
+    fn bind_addr(addr: &str) -> Result<Listener, ServerError> {
+        Listener::bind(addr).map_err(|source| ServerError::Bind {
+            addr: addr.to_string(),
+            source,
+        })
+    }
+
+    /// 绑定监听地址后在后台线程中启动Http服务，立即返回
+    ///
+    /// ```
+    /// use httpx::{HttpServer, Router};
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/", |_r, w| {
+    ///     w.write_str("hello world");
+    /// });
+    /// let mut server = HttpServer::application();
+    /// server
+    ///     .configure(HttpServer::set_addr("127.0.0.1:0"))
+    ///     .mount_route(router);
+    /// let handle = server.spawn().unwrap();
+    /// println!("listening on {:?}", handle.local_addr());
+    /// handle.shutdown().unwrap();
+    /// ```
+    pub fn spawn(mut self) -> Result<ServerHandle, ServerError> {
+        self.bind()?;
+        let local_addrs = self.local_addrs();
+        let shutdown = self.shutdown.clone();
+        let thread = thread::Builder::new()
+            .name("httpx-server".to_string())
+            .spawn(move || self.start())?;
+        Ok(ServerHandle {
+            local_addrs,
+            shutdown,
+            thread,
+        })
     }
 
     /// 设置HttpServer监听地址，默认值："127.0.0.1:8080"
+    ///
+    /// 以`unix:`开头时监听Unix domain socket，如"unix:/run/httpx.sock"，
+    /// 遗留的socket文件会在绑定前删除，server停止后同样会删除
     pub fn set_addr(addr: &str) -> impl FnOnce(&mut HttpServer) {
         // 不可直接捕获参数所有权
         let a = addr.to_owned();
         |t: &mut Self| {
-            t.addr = a;
-        }
-    }
-
-    fn executor(&self, mut stream: TcpStream) {
-        // println!("process stream");
-        let router = self.router.clone();
-        // let request = Self::parse_stream(&mut stream);
-        let mut resp = HttpResponse::from(self.response.clone());
-
-        // match router.get_handler(request.method, &request.uri) {
-        //     Ok(s) => {
-        //         // println!("{}", "executor");
-        //         resp.set_http_state_code(HttpStateCode::StatusOK);
-        //         let handler = s.handler;
-        //         handler(&request, &mut resp);
-        //     }
-        //     Err(e) => {
-        //         println!("err: {}", e);
-        //         // HttpResponse::new().write_str(&e);
-        //         // return;
-        //     }
-        // }
-
-        // let resp_str: String = resp.into();
-        // if let Err(e) = stream.write_all(resp_str.as_bytes()) {
-        //     println!("response write error: {}", e);
-        // }
-        self.pool.execute(move || {
-            let request = Self::parse_stream(&mut stream);
-            // let mut resp = HttpResponse::default();
-
-            match router.get_handler(request.method, &request.uri) {
-                Ok(s) => {
-                    // println!("{}", "executor");
-                    resp.set_http_state_code(HttpStateCode::StatusOK);
-                    let handler = s.handler;
-                    handler(&request, &mut resp);
-                }
+            t.listens[0].addr = a;
+        }
+    }
+
+    /// 添加一个监听地址，使用`mount_route`挂载的Router
+    ///
+    /// ```
+    /// use httpx::{HttpServer, Router};
+    ///
+    /// let mut admin = Router::new();
+    /// admin.get("/metrics", |_r, w| {
+    ///     w.write_str("requests 0");
+    /// });
+    /// let mut server = HttpServer::application();
+    /// server
+    ///     .configure(HttpServer::set_addr("0.0.0.0:8080"))
+    ///     .configure(HttpServer::add_addr("[::]:8080"))
+    ///     .configure(HttpServer::add_addr_with_router("127.0.0.1:9090", admin))
+    ///     .mount_route(Router::new());
+    /// ```
+    pub fn add_addr(addr: &str) -> impl FnOnce(&mut HttpServer) {
+        let listen = Listen::new(addr, None);
+        |t: &mut Self| {
+            t.listens.push(listen);
+        }
+    }
+
+    /// 添加一个使用独立Router的监听地址，如只对内网开放的管理端口
+    pub fn add_addr_with_router(addr: &str, router: Router) -> impl FnOnce(&mut HttpServer) {
+        let listen = Listen::new(addr, Some(Arc::new(router)));
+        |t: &mut Self| {
+            t.listens.push(listen);
+        }
+    }
+
+    /// 启用HTTPS，设置PEM格式的证书链和私钥文件路径，在`start`或`bind`时读取
+    ///
+    /// 客户端没有通过SNI指定域名，或没有与域名匹配的证书时使用该证书，
+    /// 通过ALPN协商`h2`(见`set_http2`)或`http/1.1`。TLS只对TCP地址生效，不影响Unix domain socket
+    ///
+    /// ```no_run
+    /// use httpx::HttpServer;
+    ///
+    /// let mut server = HttpServer::application();
+    /// server
+    ///     .configure(HttpServer::set_addr("0.0.0.0:443"))
+    ///     .configure(HttpServer::set_tls("certs/default.crt", "certs/default.key"))
+    ///     .configure(HttpServer::add_tls_cert(
+    ///         "api.example.com",
+    ///         "certs/api.crt",
+    ///         "certs/api.key",
+    ///     ));
+    /// server.start().unwrap();
+    /// ```
+    #[cfg(feature = "tls")]
+    pub fn set_tls(cert: &str, key: &str) -> impl FnOnce(&mut HttpServer) {
+        let (cert, key) = (cert.to_owned(), key.to_owned());
+        move |t: &mut Self| {
+            t.tls
+                .get_or_insert_with(TlsSettings::default)
+                .set_default(&cert, &key);
+        }
+    }
+
+    /// 启用HTTPS，添加通过SNI按域名选择的证书，域名不区分大小写
+    #[cfg(feature = "tls")]
+    pub fn add_tls_cert(server_name: &str, cert: &str, key: &str) -> impl FnOnce(&mut HttpServer) {
+        let (server_name, cert, key) = (server_name.to_owned(), cert.to_owned(), key.to_owned());
+        move |t: &mut Self| {
+            t.tls
+                .get_or_insert_with(TlsSettings::default)
+                .add_sni(&server_name, &cert, &key);
+        }
+    }
+
+    /// 设置持久连接的空闲超时时间，超时未收到新请求则关闭连接，默认值：5秒
+    ///
+    /// 超时时间为0时不启用持久连接，每个连接只处理一个请求
+    pub fn set_keep_alive_timeout(timeout: Duration) -> impl FnOnce(&mut HttpServer) {
+        move |t: &mut Self| {
+            t.keep_alive_timeout = timeout;
+        }
+    }
+
+    /// 设置读取连接上第一个请求的超时时间，避免慢速或不发送数据的客户端长期占用线程，默认值：30秒
+    ///
+    /// 之后的请求使用`set_keep_alive_timeout`设置的超时时间
+    ///
+    /// # Panics
+    ///
+    /// 超时时间为0时panic
+    pub fn set_read_timeout(timeout: Duration) -> impl FnOnce(&mut HttpServer) {
+        assert!(!timeout.is_zero(), "read timeout must be greater than zero");
+        move |t: &mut Self| {
+            t.read_timeout = timeout;
+        }
+    }
+
+    /// 是否支持HTTP/2，默认值：true
+    ///
+    /// 启用TLS时通过ALPN优先协商`h2`，明文连接还需要通过`set_h2c`启用。同一连接上的多个请求并发处理
+    pub fn set_http2(enabled: bool) -> impl FnOnce(&mut HttpServer) {
+        move |t: &mut Self| {
+            t.http2 = enabled;
+        }
+    }
+
+    /// 明文连接是否支持HTTP/2(h2c)，默认值：false，`set_http2`关闭时不生效
+    ///
+    /// 启用时，以HTTP/2连接序言开头的明文连接(prior knowledge)和带有`Upgrade: h2c`的请求切换到HTTP/2
+    pub fn set_h2c(enabled: bool) -> impl FnOnce(&mut HttpServer) {
+        move |t: &mut Self| {
+            t.h2c = enabled;
+        }
+    }
+
+    /// 设置所有HTTP/2连接同时处理的请求数上限，每个请求在独立的线程中处理，
+    /// 超出时新的流以REFUSED_STREAM拒绝，客户端可以稍后重试，默认值：256
+    pub fn set_max_http2_streams(max: usize) -> impl FnOnce(&mut HttpServer) {
+        move |t: &mut Self| {
+            t.h2_streams = Arc::new(StreamLimit::new(max));
+        }
+    }
+
+    /// 设置单个连接最多处理的请求数，默认值：100
+    pub fn set_max_requests(max: usize) -> impl FnOnce(&mut HttpServer) {
+        move |t: &mut Self| {
+            t.max_requests = max;
+        }
+    }
+
+    /// 设置停止server时等待正在处理的请求完成的最长时间，超时后强制关闭连接，默认值：30秒
+    pub fn set_shutdown_timeout(timeout: Duration) -> impl FnOnce(&mut HttpServer) {
+        move |t: &mut Self| {
+            t.shutdown_timeout = timeout;
+        }
+    }
+
+    /// 设置请求体(chunked解码后)的最大长度，超出时返回413，默认值：2MB
+    pub fn set_max_body_size(size: usize) -> impl FnOnce(&mut HttpServer) {
+        move |t: &mut Self| {
+            t.limits.max_body_size = size;
+        }
+    }
+
+    /// 注册应用共享状态，处理函数中通过`HttpRequest::state`按类型获取
+    pub fn set_state<T: Send + Sync + 'static>(state: T) -> impl FnOnce(&mut HttpServer) {
+        move |t: &mut Self| {
+            Arc::make_mut(&mut t.state).insert(state);
+        }
+    }
+
+    fn executor(&self, stream: Stream, router: Arc<Router>) {
+        let state = self.state.clone();
+        let middleware = self.middleware.clone();
+        let headers = self.headers.clone();
+        let limits = self.limits;
+        let shutdown = self.shutdown.clone();
+        let timeout = self.keep_alive_timeout;
+        let read_timeout = self.read_timeout;
+        let max_requests = match timeout.is_zero() {
+            true => 1,
+            false => self.max_requests.max(1),
+        };
+        let http2 = self.http2;
+        let h2c = self.h2c;
+        let h2_streams = self.h2_streams.clone();
+
+        let result = self.pool.execute(move || {
+            // 排队期间server已停止时直接关闭连接
+            let guard = match shutdown.register(&stream) {
+                Some(guard) => guard,
+                None => return,
+            };
+            if let Err(e) = stream.set_read_timeout(Some(read_timeout)) {
+                println!("set read timeout err: {}", e);
+                return;
+            }
+            // 客户端可能在排队期间已经断开
+            let remote_addr = match stream.peer_addr() {
+                Ok(addr) => addr.to_string(),
                 Err(e) => {
-                    println!("err: {}", e);
-                    // HttpResponse::new().write_str(&e);
-                    // return;
+                    println!("{}", ServerError::Io(e));
+                    return;
+                }
+            };
+            let service = |request: &mut HttpRequest, resp: &mut HttpResponse| {
+                request.set_remote_addr(&remote_addr);
+                request.state = state.clone();
+                resp.shutdown = Some(shutdown.clone());
+                Self::dispatch(&router, &middleware, request, resp);
+            };
+            let mut reader = RequestReader::new(&stream, limits);
+
+            // 以连接序言开头时直接使用HTTP/2(prior knowledge，或TLS通过ALPN协商为h2)
+            if http2 && (h2c || stream.is_tls()) {
+                match reader.starts_with(h2::PREFACE) {
+                    Ok(true) => {
+                        let (_, buf) = reader.into_parts();
+                        let conn =
+                            Connection::new(&stream, limits, max_requests, &headers, h2_streams);
+                        conn.serve(Cursor::new(buf).chain(&stream), None, &service);
+                        return;
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        println!("{}", ServerError::Io(e));
+                        return;
+                    }
                 }
             }
 
-            let resp_str: String = resp.into();
-            if let Err(e) = stream.write_all(resp_str.as_bytes()) {
-                println!("response write error: {}", e);
+            for served in 1..=max_requests {
+                let mut resp = HttpResponse::new();
+                resp.headers.extend(headers.clone());
+                let mut request = match reader.read_request() {
+                    Ok(Some(request)) => request,
+                    // 客户端关闭连接或空闲超时
+                    Ok(None) => return,
+                    Err(e) => {
+                        let code = e.state_code();
+                        println!("{}", ServerError::Parse(e));
+                        if let Some(code) = code {
+                            resp.set_http_state_code(code);
+                            resp.insert_header("Connection", "close");
+                            Self::write_response(&stream, resp, true, true);
+                        }
+                        return;
+                    }
+                };
+
+                // h2c升级只用于明文连接，当前请求在HTTP/2的流1上响应
+                if http2 && h2c && !stream.is_tls() {
+                    if let Some(settings) = h2::h2c_upgrade(&request) {
+                        if let Err(e) = (&stream).write_all(
+                            b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n",
+                        ) {
+                            println!("{}", ServerError::Io(e));
+                            return;
+                        }
+                        let (_, buf) = reader.into_parts();
+                        let conn =
+                            Connection::new(&stream, limits, max_requests, &headers, h2_streams);
+                        conn.serve(
+                            Cursor::new(buf).chain(&stream),
+                            Some((request, settings)),
+                            &service,
+                        );
+                        return;
+                    }
+                }
+
+                service(&mut request, &mut resp);
+
+                // WebSocket握手成功，在线程池之外的独立线程中处理该连接，停止server时同样会关闭
+                if let Some(upgrade) = resp.upgrade.take().filter(|_| resp.status_code == 101) {
+                    if !Self::write_response(&stream, resp, true, true) {
+                        return;
+                    }
+                    if let Err(e) = stream.set_read_timeout(None) {
+                        println!("set read timeout err: {}", e);
+                        return;
+                    }
+                    let (_, buf) = reader.into_parts();
+                    thread::spawn(move || {
+                        let _guard = guard;
+                        let ws = WebSocket::new(stream, buf, limits.max_body_size);
+                        upgrade.run(&request, ws);
+                    });
+                    return;
+                }
+
+                // HTTP/1.0 不支持chunked，长度未知的响应体需要关闭连接来标识结束
+                let chunked = request.version != Version::V1_0;
+                let with_body = request.method != Method::HEAD;
+
+                // Server-Sent Events可能长期不结束，同样在独立线程中发送，响应结束后关闭连接
+                if resp.event_stream {
+                    resp.insert_header("Connection", "close");
+                    thread::spawn(move || {
+                        let _guard = guard;
+                        Self::write_response(&stream, resp, chunked, with_body);
+                    });
+                    return;
+                }
+                let keep_alive = served < max_requests
+                    && !shutdown.is_shutdown()
+                    && Self::keep_alive(&request)
+                    && (chunked || !with_body || resp.body.len().is_some())
+                    && !resp
+                        .headers
+                        .get("Connection")
+                        .is_some_and(|v| v.eq_ignore_ascii_case("close"));
+                if !keep_alive {
+                    resp.insert_header("Connection", "close");
+                } else if request.version == Version::V1_0 {
+                    resp.insert_header("Connection", "keep-alive");
+                }
+
+                if !Self::write_response(&stream, resp, chunked, with_body) || !keep_alive {
+                    return;
+                }
+                // 之后的请求按持久连接的空闲超时等待
+                if served == 1 && timeout != read_timeout {
+                    if let Err(e) = stream.set_read_timeout(Some(timeout)) {
+                        println!("set read timeout err: {}", e);
+                        return;
+                    }
+                }
             }
         });
+        // 任务连同连接一起被丢弃
+        if let Err(e) = result {
+            println!("{}", ServerError::Io(e));
+        }
     }
 
-    fn parse_stream(stream: &mut TcpStream) -> HttpRequest {
-        let mut buf: Vec<u8> = Vec::new();
-        let _len = Self::parse_stream_to_request(stream, &mut buf);
-        let mut request = HttpRequest::from(String::from_utf8_lossy(buf.as_slice()).to_string());
-        request.set_remote_addr(stream.peer_addr().unwrap().to_string().as_str());
-        // s.router.get_handler(request.method, &request.uri);
-        request
+    fn dispatch(
+        router: &Router,
+        middleware: &[Arc<dyn Middleware>],
+        request: &mut HttpRequest,
+        resp: &mut HttpResponse,
+    ) {
+        let endpoint =
+            |request: &mut HttpRequest, resp: &mut HttpResponse| Self::route(router, request, resp);
+        Next::new(middleware, &endpoint).run(request, resp);
     }
 
-    // 读取http请求信息，这个函数可能存在一些bug，比如读取到的数据不完整，需要继续读取
-    fn parse_stream_to_request(stream: &mut TcpStream, buf: &mut Vec<u8>) -> usize {
-        let mut req = [0; 1024];
-        let mut pack_len: usize = 0;
-        // println!("remote ip : {}", stream.peer_addr().unwrap().to_string());
-        while let Ok(len) = stream.read(&mut req) {
-            // println!("pack len : {}", len);
-            if len == 0 {
-                break;
+    fn route(router: &Router, request: &mut HttpRequest, resp: &mut HttpResponse) {
+        match router.get_handler(request.method.clone(), &request.uri) {
+            Ok((s, params)) => {
+                request.path_params = params;
+                resp.set_http_state_code(HttpStateCode::StatusOK);
+                router.call(s, request, resp);
             }
-            pack_len += len;
-            buf.extend_from_slice(&req[..len]);
-            if len < req.len() {
-                break;
+            // 没有显式注册OPTIONS时，根据path已注册的method自动响应
+            Err(RouteError::MethodNotAllowed(methods)) if request.method == Method::OPTIONS => {
+                Self::insert_allow(resp, &methods);
+                resp.set_http_state_code(HttpStateCode::StatusNoContent);
+            }
+            Err(e) => {
+                println!("err: {}", e);
+                if let RouteError::MethodNotAllowed(methods) = &e {
+                    Self::insert_allow(resp, methods);
+                }
+                resp.set_http_state_code(e.state_code());
             }
         }
-        pack_len
+    }
+
+    // OPTIONS总是由server自动响应，需加入Allow中
+    fn insert_allow(resp: &mut HttpResponse, methods: &[Method]) {
+        let mut allow: Vec<String> = methods.iter().map(|m| m.to_string()).collect();
+        if !methods.contains(&Method::OPTIONS) {
+            allow.push(Method::OPTIONS.to_string());
+        }
+        resp.insert_header("Allow", &allow.join(", "));
+    }
+
+    // HTTP/1.1 默认保持连接，HTTP/1.0 需显式声明 keep-alive
+    fn keep_alive(request: &HttpRequest) -> bool {
+        let has_token = |token: &str| {
+            request.get_header("Connection").is_some_and(|v| {
+                v.split(',')
+                    .any(|option| option.trim().eq_ignore_ascii_case(token))
+            })
+        };
+        match request.version {
+            Version::V1_0 => has_token("keep-alive"),
+            _ => !has_token("close"),
+        }
+    }
+
+    fn write_response(stream: &Stream, resp: HttpResponse, chunked: bool, with_body: bool) -> bool {
+        if let Err(e) = resp.send(stream, chunked, with_body) {
+            println!("{}", ServerError::Io(e));
+            return false;
+        }
+        true
     }
 
     fn default() -> Self {
         let cpu_num = num_cpus::get();
         HttpServer {
-            addr: "127.0.0.1:8080".parse().unwrap(),
+            listens: vec![Listen::new("127.0.0.1:8080", None)],
             router: Arc::new(Router::new()),
             pool: ThreadPool::new(cpu_num + 1),
-            response: HttpResponse::default(),
+            headers: HashMap::new(),
+            state: Arc::new(AppState::new()),
+            middleware: Arc::new(Vec::new()),
+            limits: ParseLimits::default(),
+            keep_alive_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(30),
+            max_requests: 100,
+            shutdown: ShutdownHandle::default(),
+            shutdown_timeout: Duration::from_secs(30),
+            http2: true,
+            h2c: false,
+            h2_streams: Arc::new(StreamLimit::new(256)),
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 }
@@ -331,3 +972,382 @@ impl HttpServer {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod test_http_server {
+    use std::{
+        io::{ErrorKind, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{HttpServer, ServerError};
+    use crate::{net::Stream, HttpRequest, HttpResponse, HttpStateCode, Next, Router};
+
+    // 将一个已建立的连接交给server处理，返回客户端读到的全部响应
+    fn exchange(server: &mut HttpServer, data: &str) -> String {
+        let mut router = Router::new();
+        router.get("/", |_r, w| {
+            w.write_str("ok");
+        });
+        router.get("/state", |r, w| {
+            w.write_str(r.state::<&str>().unwrap_or(&"none"));
+        });
+        router.get("/stream", |_r, w| {
+            w.write_chunks((0..3).map(|i| i.to_string().into_bytes()));
+        });
+        server.mount_route(router);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.executor(Stream::Tcp(stream), server.router.clone());
+
+        client.write_all(data.as_bytes()).unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let mut server = HttpServer::application();
+        let resp = exchange(
+            &mut server,
+            "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(resp.matches("HTTP/1.1 200 OK").count(), 2);
+        assert_eq!(resp.matches("Connection: close").count(), 1);
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        // 不启用持久连接时，读取请求仍有超时
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_keep_alive_timeout(Duration::ZERO))
+            .configure(HttpServer::set_read_timeout(Duration::from_millis(100)));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.executor(Stream::Tcp(stream), server.router.clone());
+
+        client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let start = Instant::now();
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_http_1_0() {
+        let mut server = HttpServer::application();
+        let resp = exchange(
+            &mut server,
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n",
+        );
+        assert_eq!(resp.matches("200 OK").count(), 2);
+        assert_eq!(resp.matches("Connection: keep-alive").count(), 1);
+    }
+
+    #[test]
+    fn test_streaming_response() {
+        let mut server = HttpServer::application();
+        let resp = exchange(
+            &mut server,
+            "GET /stream HTTP/1.1\r\n\r\nGET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+        );
+        assert!(resp
+            .contains("Transfer-Encoding: chunked\r\n\r\n1\r\n0\r\n1\r\n1\r\n1\r\n2\r\n0\r\n\r\n"));
+        assert!(resp.ends_with("\r\n\r\n012"));
+        assert_eq!(resp.matches("Connection: close").count(), 1);
+        assert_eq!(resp.matches("200 OK").count(), 2);
+    }
+
+    #[test]
+    fn test_state() {
+        let mut server = HttpServer::application();
+        server.configure(HttpServer::set_state("shared"));
+        let resp = exchange(
+            &mut server,
+            "GET /state HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(resp.ends_with("\r\n\r\nshared"));
+    }
+
+    #[test]
+    fn test_not_found_and_method_not_allowed() {
+        let mut server = HttpServer::application();
+        let resp = exchange(
+            &mut server,
+            "GET /missing HTTP/1.1\r\n\r\nPATCH / HTTP/1.1\r\n\r\nPURGE / HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(resp.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(resp.contains("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(resp.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+        assert!(resp.contains("HTTP/1.1 501 Not Implemented\r\n"));
+    }
+
+    #[test]
+    fn test_head_and_options() {
+        let mut server = HttpServer::application();
+        let resp = exchange(
+            &mut server,
+            "HEAD / HTTP/1.1\r\n\r\nOPTIONS /state HTTP/1.1\r\n\r\nHEAD /stream HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        let parts: Vec<&str> = resp.split("HTTP/1.1 ").skip(1).collect();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[0].starts_with("200 OK\r\n"));
+        assert!(parts[0].ends_with("Content-Length: 2\r\n\r\n"));
+        assert!(parts[1].starts_with("204 No Content\r\n"));
+        assert!(parts[1].contains("Allow: GET, HEAD, OPTIONS\r\n"));
+        assert!(parts[1].ends_with("\r\n\r\n"));
+        assert!(!parts[1].contains("Content-Length"));
+        assert!(parts[2].ends_with("Transfer-Encoding: chunked\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_server_middleware() {
+        let mut server = HttpServer::application();
+        server.middleware(
+            |req: &mut HttpRequest, resp: &mut HttpResponse, next: Next| {
+                if req.get_uri() == "/blocked" {
+                    resp.set_http_state_code(HttpStateCode::StatusForbidden);
+                    return;
+                }
+                next.run(req, resp);
+                resp.insert_header("X-Middleware", "1");
+            },
+        );
+        let resp = exchange(
+            &mut server,
+            "GET /blocked HTTP/1.1\r\n\r\nGET /missing HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        assert!(resp.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(resp.contains("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(resp.matches("X-Middleware: 1").count(), 1);
+    }
+
+    #[test]
+    fn test_max_requests() {
+        let mut server = HttpServer::application();
+        server.configure(HttpServer::set_max_requests(2));
+        let resp = exchange(
+            &mut server,
+            "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(resp.matches("200 OK").count(), 2);
+        assert!(resp.ends_with("ok"));
+    }
+
+    #[test]
+    fn test_graceful_shutdown() {
+        let mut router = Router::new();
+        router.get("/", |_r, w| {
+            w.write_str("ok");
+        });
+        router.get("/slow", |_r, w| {
+            thread::sleep(Duration::from_millis(300));
+            w.write_str("slow");
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .configure(HttpServer::set_keep_alive_timeout(Duration::from_secs(30)))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        // 空闲的持久连接
+        let mut idle = TcpStream::connect(addr).unwrap();
+        idle.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = [0; 1024];
+        let n = idle.read(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf[..n]).ends_with("\r\n\r\nok"));
+        // 处理中的请求
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        let begin = Instant::now();
+        handle.shutdown().unwrap();
+        assert!(begin.elapsed() < Duration::from_secs(5));
+        let mut resp = String::new();
+        busy.read_to_string(&mut resp).unwrap();
+        assert!(resp.contains("Connection: close\r\n"));
+        assert!(resp.ends_with("slow"));
+        assert_eq!(idle.read(&mut buf).unwrap(), 0);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn test_from_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut router = Router::new();
+        router.get("/", |_r, w| {
+            w.write_str("ok");
+        });
+        let mut server = HttpServer::from_listener(listener);
+        server.mount_route(router);
+        assert_eq!(server.local_addr(), Some(addr));
+
+        let handle = server.spawn().unwrap();
+        assert_eq!(handle.local_addr(), Some(addr));
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_bind_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let mut server = HttpServer::application();
+        server.configure(HttpServer::set_addr(&addr));
+        match server.start() {
+            Err(ServerError::Bind { addr: a, source }) => {
+                assert_eq!(a, addr);
+                assert_eq!(source.kind(), ErrorKind::AddrInUse);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut client = TcpStream::connect(addr).unwrap();
+        write!(client, "GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", path).unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        let mut router = Router::new();
+        router.get("/panic", |_r, _w| {
+            panic!("handler panic");
+        });
+        router.get("/", |_r, w| {
+            w.write_str("ok");
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        // panic的次数多于工作线程数，连接被关闭但工作线程仍然可用
+        for _ in 0..num_cpus::get() + 2 {
+            assert_eq!(get(addr, "/panic"), "");
+        }
+        assert!(get(addr, "/").ends_with("\r\n\r\nok"));
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_multiple_addrs() {
+        let mut router = Router::new();
+        router.get("/", |_r, w| {
+            w.write_str("public");
+        });
+        let mut admin = Router::new();
+        admin.get("/", |_r, w| {
+            w.write_str("admin");
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .configure(HttpServer::add_addr("127.0.0.1:0"))
+            .configure(HttpServer::add_addr_with_router("127.0.0.1:0", admin))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+        let addrs = handle.local_addrs().to_vec();
+        assert_eq!(addrs.len(), 3);
+        assert_eq!(handle.local_addr(), Some(addrs[0]));
+        assert!(get(addrs[0], "/").ends_with("\r\n\r\npublic"));
+        assert!(get(addrs[1], "/").ends_with("\r\n\r\npublic"));
+        assert!(get(addrs[2], "/").ends_with("\r\n\r\nadmin"));
+        handle.shutdown().unwrap();
+        for addr in addrs {
+            assert!(TcpStream::connect(addr).is_err());
+        }
+    }
+
+    #[test]
+    fn test_dual_stack() {
+        // 环境不支持IPv6时跳过
+        if TcpListener::bind("[::1]:0").is_err() {
+            return;
+        }
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr(&format!("127.0.0.1:{}", port)))
+            .configure(HttpServer::add_addr(&format!("[::1]:{}", port)))
+            .mount_route(Router::new());
+        let handle = server.spawn().unwrap();
+        assert_eq!(handle.local_addrs()[1].port(), port);
+        assert!(handle.local_addrs()[1].is_ipv6());
+        for addr in handle.local_addrs() {
+            assert!(get(*addr, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        }
+        handle.shutdown().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket() {
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let path = std::env::temp_dir().join(format!("httpx-test-{}.sock", std::process::id()));
+        // 遗留的socket文件
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let mut router = Router::new();
+        router.get("/addr", |r, w| {
+            w.write_str(&r.get_remote_addr());
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr(&format!("unix:{}", path.display())))
+            .configure(HttpServer::add_addr("127.0.0.1:0"))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client
+            .write_all(b"GET /addr HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.ends_with(&format!("\r\n\r\nunix:{}", path.display())));
+        assert!(get(handle.local_addr().unwrap(), "/addr").contains("\r\n\r\n127.0.0.1:"));
+
+        handle.shutdown().unwrap();
+        assert!(!path.exists());
+
+        // 只监听Unix domain socket时没有TCP地址
+        let mut server = HttpServer::application();
+        server.configure(HttpServer::set_addr(&format!("unix:{}", path.display())));
+        let handle = server.spawn().unwrap();
+        assert_eq!(handle.local_addr(), None);
+        handle.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    net::Shutdown,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::net::{LocalAddr, Stream};
+
+/// 停止HttpServer的句柄，可以clone后传递到其他线程
+///
+/// 调用`shutdown`后，server停止接受新连接，关闭空闲的持久连接，
+/// 正在处理的请求完成后以`Connection: close`响应并关闭连接，
+/// 全部连接关闭或超过`HttpServer::set_shutdown_timeout`设置的时间后`start`返回
+///
+/// ```no_run
+/// use std::{thread, time::Duration};
+/// use httpx::HttpServer;
+///
+/// let server = HttpServer::application();
+/// let handle = server.shutdown_handle();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(60));
+///     handle.shutdown();
+/// });
+/// server.start().unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    shutdown: AtomicBool,
+    // 阻塞在accept上的监听地址，关闭时连接一次以唤醒
+    listeners: Mutex<Vec<LocalAddr>>,
+    connections: Mutex<HashMap<usize, Stream>>,
+    closed: Condvar,
+    next_id: AtomicUsize,
+}
+
+impl ShutdownHandle {
+    /// 通知server停止，立即返回，不等待连接关闭
+    pub fn shutdown(&self) {
+        if self.inner.shutdown.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for addr in self.inner.listeners.lock().unwrap().iter() {
+            addr.wake();
+        }
+        // 关闭读方向，阻塞在读取下一个请求上的空闲连接会立即读到EOF，
+        // 正在处理中的请求仍可以写出响应
+        for stream in self.inner.connections.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// 收到SIGINT或SIGTERM时停止server
+    #[cfg(feature = "signal")]
+    pub fn shutdown_on_signal(&self) -> std::io::Result<()> {
+        use signal_hook::{
+            consts::{SIGINT, SIGTERM},
+            iterator::Signals,
+        };
+
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let handle = self.clone();
+        std::thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                handle.shutdown();
+            }
+        });
+        Ok(())
+    }
+
+    // 登记监听地址，返回false表示已经停止，不应再进入accept
+    pub(crate) fn add_listener(&self, addr: LocalAddr) -> bool {
+        self.inner.listeners.lock().unwrap().push(addr);
+        !self.is_shutdown()
+    }
+
+    // 登记连接，连接处理结束时ConnectionGuard被drop，自动注销
+    pub(crate) fn register(&self, stream: &Stream) -> Option<ConnectionGuard> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut connections = self.inner.connections.lock().unwrap();
+        if self.is_shutdown() {
+            return None;
+        }
+        if let Ok(stream) = stream.try_clone() {
+            connections.insert(id, stream);
+        }
+        Some(ConnectionGuard {
+            handle: self.clone(),
+            id,
+        })
+    }
+
+    // 等待全部连接关闭，超时后强制关闭剩余的连接
+    pub(crate) fn wait(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut connections = self.inner.connections.lock().unwrap();
+        while !connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                println!(
+                    "shutdown timeout, closing {} connections",
+                    connections.len()
+                );
+                for stream in connections.values() {
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+                return;
+            }
+            connections = self
+                .inner
+                .closed
+                .wait_timeout(connections, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+pub(crate) struct ConnectionGuard {
+    handle: ShutdownHandle,
+    id: usize,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let inner = &self.handle.inner;
+        inner.connections.lock().unwrap().remove(&self.id);
+        inner.closed.notify_all();
+    }
+}
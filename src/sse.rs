@@ -0,0 +1,299 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    time::Duration,
+};
+
+use crate::ShutdownHandle;
+
+// 等待事件时检查server是否已停止的间隔
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+/// Server-Sent Events中的一个事件，见`HttpResponse::event_stream`
+///
+/// ```
+/// use std::time::Duration;
+/// use httpx::Event;
+///
+/// let event = Event::new("{\"cpu\": 12}")
+///     .event("stats")
+///     .id("42")
+///     .retry(Duration::from_secs(3));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    event: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// 创建携带data的事件，多行data会拆分为多个`data`字段，客户端收到的仍是原始的多行文本
+    pub fn new(data: &str) -> Self {
+        Event {
+            data: Some(data.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// 设置事件类型，客户端通过`addEventListener(name)`接收，不设置时为`message`
+    pub fn event(mut self, name: &str) -> Self {
+        self.event = Some(single_line(name));
+        self
+    }
+
+    /// 设置事件id，客户端重连时通过`Last-Event-ID`请求头带回
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    /// 设置客户端断开后重连的等待时间
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut frame = String::new();
+        if let Some(event) = &self.event {
+            frame.push_str(&format!("event: {}\n", event));
+        }
+        if let Some(id) = &self.id {
+            frame.push_str(&format!("id: {}\n", id));
+        }
+        if let Some(retry) = self.retry {
+            frame.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        if let Some(data) = &self.data {
+            for line in data.replace("\r\n", "\n").split(['\n', '\r']) {
+                frame.push_str(&format!("data: {}\n", line));
+            }
+        }
+        frame.push('\n');
+        frame.into_bytes()
+    }
+}
+
+// event和id字段中的换行会破坏事件格式，直接去掉
+fn single_line(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !matches!(c, '\r' | '\n'))
+        .collect()
+}
+
+/// 客户端已断开，或响应已经结束
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Disconnected;
+
+impl Display for Disconnected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "event stream disconnected")
+    }
+}
+
+impl Error for Disconnected {}
+
+/// 向Server-Sent Events响应推送事件的句柄，可以clone后传递到其他线程
+///
+/// 全部EventSender被drop后响应结束
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    sender: Sender<Vec<u8>>,
+}
+
+impl EventSender {
+    /// 推送一个事件，客户端断开后返回`Disconnected`
+    ///
+    /// 断开是在写入事件或心跳失败时发现的，因此最多会延迟一个心跳间隔
+    pub fn send(&self, event: Event) -> Result<(), Disconnected> {
+        self.sender.send(event.encode()).map_err(|_| Disconnected)
+    }
+}
+
+// 响应体，没有事件时按心跳间隔发送注释行，停止server后结束
+pub(crate) struct Events {
+    receiver: Receiver<Vec<u8>>,
+    heartbeat: Duration,
+    shutdown: Option<ShutdownHandle>,
+    started: bool,
+}
+
+impl Iterator for Events {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        // 先发送一个注释行，客户端可以立即收到响应头
+        if !self.started {
+            self.started = true;
+            return Some(b":\n\n".to_vec());
+        }
+        let mut waited = Duration::ZERO;
+        loop {
+            if self.shutdown.as_ref().is_some_and(|s| s.is_shutdown()) {
+                return None;
+            }
+            let wait = match self.heartbeat.is_zero() {
+                true => SHUTDOWN_POLL,
+                false => (self.heartbeat - waited).min(SHUTDOWN_POLL),
+            };
+            match self.receiver.recv_timeout(wait) {
+                Ok(frame) => return Some(frame),
+                Err(RecvTimeoutError::Disconnected) => return None,
+                Err(RecvTimeoutError::Timeout) => {
+                    waited += wait;
+                    if !self.heartbeat.is_zero() && waited >= self.heartbeat {
+                        return Some(b":\n\n".to_vec());
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn channel(
+    heartbeat: Duration,
+    shutdown: Option<ShutdownHandle>,
+) -> (EventSender, Events) {
+    let (sender, receiver) = mpsc::channel();
+    let events = Events {
+        receiver,
+        heartbeat,
+        shutdown,
+        started: false,
+    };
+    (EventSender { sender }, events)
+}
+
+#[cfg(test)]
+mod test_sse {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{Disconnected, Event};
+    use crate::{HttpServer, Router};
+
+    #[test]
+    fn test_encode() {
+        let event = Event::new("line1\nline2\r\n")
+            .event("up\ndate")
+            .id("7")
+            .retry(Duration::from_millis(1500));
+        assert_eq!(
+            String::from_utf8(event.encode()).unwrap(),
+            "event: update\nid: 7\nretry: 1500\ndata: line1\ndata: line2\ndata: \n\n"
+        );
+        assert_eq!(
+            Event::default().retry(Duration::from_secs(1)).encode(),
+            b"retry: 1000\n\n"
+        );
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let (done_tx, done_rx) = mpsc::channel();
+        let mut router = Router::new();
+        router.get("/events", |_r, w| {
+            let events = w.event_stream(Duration::ZERO);
+            thread::spawn(move || {
+                for i in 0..2 {
+                    events.send(Event::new(&i.to_string())).unwrap();
+                }
+            });
+        });
+        router.get("/forever", move |_r, w| {
+            let events = w.event_stream(Duration::from_millis(20));
+            let done = done_tx.clone();
+            thread::spawn(move || {
+                events.send(Event::new("hello")).unwrap();
+                // 客户端断开后写入失败，响应结束，之后send返回Disconnected
+                while events.send(Event::new("tick")).is_ok() {
+                    thread::sleep(Duration::from_millis(20));
+                }
+                done.send(events.send(Event::new("late"))).unwrap();
+            });
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.contains("Content-Type: text/event-stream\r\n"));
+        assert!(resp.contains("Cache-Control: no-cache\r\n"));
+        assert!(resp
+            .ends_with("\r\n\r\n3\r\n:\n\n\r\n9\r\ndata: 0\n\n\r\n9\r\ndata: 1\n\n\r\n0\r\n\r\n"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"GET /forever HTTP/1.1\r\n\r\n").unwrap();
+        let mut buf = Vec::new();
+        let mut b = [0; 1];
+        while !String::from_utf8_lossy(&buf).contains("data: hello\n\n") {
+            stream.read_exact(&mut b).unwrap();
+            buf.push(b[0]);
+        }
+        drop(stream);
+        assert_eq!(
+            done_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Err(Disconnected)
+        );
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let (sender_tx, sender_rx) = mpsc::channel();
+        let mut router = Router::new();
+        router.get("/events", move |_r, w| {
+            sender_tx.send(w.event_stream(Duration::ZERO)).unwrap();
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .configure(HttpServer::set_shutdown_timeout(Duration::from_secs(10)))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+
+        let mut stream = TcpStream::connect(handle.local_addr().unwrap()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        // 保留EventSender，响应只会因停止server而结束
+        let _events = sender_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        let mut buf = Vec::new();
+        let mut b = [0; 1];
+        while !buf.ends_with(b":\n\n\r\n") {
+            stream.read_exact(&mut b).unwrap();
+            buf.push(b[0]);
+        }
+
+        let start = Instant::now();
+        handle.shutdown().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "0\r\n\r\n");
+    }
+}
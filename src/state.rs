@@ -0,0 +1,91 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Debug,
+    sync::Arc,
+};
+
+/// 按类型存储的应用共享状态，每种类型只保存一个值
+///
+/// ```
+/// use httpx::{HttpServer, Router};
+///
+/// struct Config {
+///     name: String,
+/// }
+///
+/// let mut router = Router::new();
+/// router.get("/", |r, w| {
+///     let config = r.state::<Config>().unwrap();
+///     w.write_str(&config.name);
+/// });
+/// let mut server = HttpServer::application();
+/// server
+///     .configure(HttpServer::set_state(Config { name: "httpx".to_string() }))
+///     .mount_route(router);
+/// ```
+#[derive(Default, Clone)]
+pub struct AppState {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        AppState::default()
+    }
+
+    /// 存入一个值，同类型的旧值会被替换
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.map.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    /// 按类型获取值
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl Debug for AppState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AppState {{ len: {} }}", self.map.len())
+    }
+}
+
+/// 持有相同的状态实例时相等
+impl PartialEq for AppState {
+    fn eq(&self, other: &Self) -> bool {
+        self.map.len() == other.map.len()
+            && self
+                .map
+                .iter()
+                .all(|(k, v)| other.map.get(k).is_some_and(|o| Arc::ptr_eq(v, o)))
+    }
+}
+
+#[cfg(test)]
+mod test_state {
+    use super::AppState;
+
+    #[test]
+    fn test_app_state() {
+        let mut state = AppState::new();
+        state.insert(1_u32);
+        state.insert("db".to_string());
+        state.insert(2_u32);
+        assert_eq!(state.len(), 2);
+        assert_eq!(state.get::<u32>(), Some(&2));
+        assert_eq!(state.get::<String>().map(|s| s.as_str()), Some("db"));
+        assert_eq!(state.get::<u64>(), None);
+        assert_eq!(state.clone(), state);
+    }
+}
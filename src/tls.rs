@@ -0,0 +1,409 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    fs::File,
+    io::{self, BufReader, ErrorKind, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Mutex},
+};
+
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::CertificateDer,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig, ServerConnection,
+};
+
+use crate::ServerError;
+
+/// 通过ALPN声明支持的协议，启用HTTP/2时优先使用h2
+const ALPN_PROTOCOLS: &[&[u8]] = &[b"h2", b"http/1.1"];
+
+/// `HttpServer::set_tls`和`HttpServer::add_tls_cert`设置的证书，start时加载
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TlsSettings {
+    // 客户端没有发送SNI或没有匹配的证书时使用
+    default: Option<CertPaths>,
+    sni: Vec<(String, CertPaths)>,
+}
+
+#[derive(Debug, Clone)]
+struct CertPaths {
+    cert: String,
+    key: String,
+}
+
+impl TlsSettings {
+    pub(crate) fn set_default(&mut self, cert: &str, key: &str) {
+        self.default = Some(CertPaths {
+            cert: cert.to_string(),
+            key: key.to_string(),
+        });
+    }
+
+    pub(crate) fn add_sni(&mut self, server_name: &str, cert: &str, key: &str) {
+        let paths = CertPaths {
+            cert: cert.to_string(),
+            key: key.to_string(),
+        };
+        self.sni.push((server_name.to_ascii_lowercase(), paths));
+    }
+
+    /// 读取PEM格式的证书链和私钥，生成rustls的配置
+    pub(crate) fn build(&self, http2: bool) -> Result<Arc<ServerConfig>, ServerError> {
+        let provider = Arc::new(ring::default_provider());
+        let mut resolver = CertResolver::default();
+        if let Some(paths) = &self.default {
+            resolver.default = Some(Self::load(&provider, paths)?);
+        }
+        for (server_name, paths) in self.sni.iter() {
+            let key = Self::load(&provider, paths)?;
+            resolver.sni.insert(server_name.clone(), key);
+        }
+
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| ServerError::Tls(e.to_string()))?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+        config.alpn_protocols = ALPN_PROTOCOLS
+            .iter()
+            .filter(|&&p| http2 || p != b"h2")
+            .map(|p| p.to_vec())
+            .collect();
+        Ok(Arc::new(config))
+    }
+
+    fn load(
+        provider: &CryptoProvider,
+        paths: &CertPaths,
+    ) -> Result<Arc<CertifiedKey>, ServerError> {
+        let open = |path: &str| {
+            File::open(path)
+                .map(BufReader::new)
+                .map_err(|e| ServerError::Tls(format!("open {} failed: {}", path, e)))
+        };
+
+        let certs = rustls_pemfile::certs(&mut open(&paths.cert)?)
+            .collect::<Result<Vec<CertificateDer>, _>>()
+            .map_err(|e| ServerError::Tls(format!("read {} failed: {}", paths.cert, e)))?;
+        if certs.is_empty() {
+            return Err(ServerError::Tls(format!(
+                "no certificate found in {}",
+                paths.cert
+            )));
+        }
+        let key = rustls_pemfile::private_key(&mut open(&paths.key)?)
+            .map_err(|e| ServerError::Tls(format!("read {} failed: {}", paths.key, e)))?
+            .ok_or_else(|| ServerError::Tls(format!("no private key found in {}", paths.key)))?;
+        let key = provider
+            .key_provider
+            .load_private_key(key)
+            .map_err(|e| ServerError::Tls(format!("load {} failed: {}", paths.key, e)))?;
+        Ok(Arc::new(CertifiedKey::new(certs, key)))
+    }
+}
+
+// 按SNI选择证书，没有匹配时使用默认证书
+#[derive(Debug, Default)]
+struct CertResolver {
+    default: Option<Arc<CertifiedKey>>,
+    sni: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.sni.get(&name.to_ascii_lowercase()))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+/// TLS连接，握手在第一次读写时进行
+///
+/// 读取socket时不持有锁，HTTP/2的请求处理线程可以在读取线程阻塞时写出响应
+pub(crate) struct TlsStream {
+    conn: Mutex<ServerConnection>,
+    sock: TcpStream,
+}
+
+impl TlsStream {
+    pub(crate) fn new(config: Arc<ServerConfig>, sock: TcpStream) -> io::Result<Self> {
+        let conn =
+            ServerConnection::new(config).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        Ok(TlsStream {
+            conn: Mutex::new(conn),
+            sock,
+        })
+    }
+
+    pub(crate) fn sock(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    // 从socket读取一次数据交给rustls处理，返回读取的字节数，0表示连接已关闭
+    fn read_tls(&self) -> io::Result<usize> {
+        let mut buf = [0; 8192];
+        let n = (&self.sock).read(&mut buf)?;
+        let mut conn = self.conn.lock().unwrap();
+        let mut data = &buf[..n];
+        while !data.is_empty() {
+            conn.read_tls(&mut data)?;
+            if let Err(e) = conn.process_new_packets() {
+                // 尽量将alert发送给客户端
+                let _ = self.write_tls(&mut conn);
+                return Err(io::Error::new(ErrorKind::InvalidData, e));
+            }
+        }
+        // 握手消息和session ticket
+        self.write_tls(&mut conn)?;
+        Ok(n)
+    }
+
+    fn write_tls(&self, conn: &mut ServerConnection) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut &self.sock)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for TlsStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TlsStream {{ sock: {:?} }}", self.sock)
+    }
+}
+
+impl Read for &TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.conn.lock().unwrap().reader().read(buf) {
+                // 客户端没有发送close_notify就关闭了连接，按正常关闭处理
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            if self.read_tls()? == 0 {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+impl Write for &TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        self.write_tls(&mut conn)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        conn.writer().flush()?;
+        self.write_tls(&mut conn)
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        if let Ok(conn) = self.conn.get_mut() {
+            conn.send_close_notify();
+            while conn.wants_write() {
+                if conn.write_tls(&mut &self.sock).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_tls {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use rustls::{
+        crypto::ring, pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore,
+        StreamOwned,
+    };
+
+    use crate::{h2::test_h2::Client, HttpServer, Router, ServerError};
+
+    // drop时删除写入临时目录的证书和私钥文件
+    struct Cert {
+        cert: rcgen::Certificate,
+        cert_path: String,
+        key_path: String,
+    }
+
+    impl Drop for Cert {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.cert_path);
+            let _ = std::fs::remove_file(&self.key_path);
+        }
+    }
+
+    fn self_signed(name: &str) -> Cert {
+        // 测试并行执行，文件名需要唯一
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let key = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let dir = std::env::temp_dir();
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = |ext: &str| -> PathBuf {
+            dir.join(format!(
+                "httpx-{}-{}-{}.{}",
+                std::process::id(),
+                id,
+                name,
+                ext
+            ))
+        };
+        std::fs::write(path("crt"), key.cert.pem()).unwrap();
+        std::fs::write(path("key"), key.key_pair.serialize_pem()).unwrap();
+        Cert {
+            cert: key.cert,
+            cert_path: path("crt").display().to_string(),
+            key_path: path("key").display().to_string(),
+        }
+    }
+
+    fn connect(
+        roots: &[&Cert],
+        addr: std::net::SocketAddr,
+        name: &str,
+        alpn: &[&[u8]],
+    ) -> StreamOwned<ClientConnection, TcpStream> {
+        let mut store = RootCertStore::empty();
+        for root in roots {
+            store.add(root.cert.der().clone()).unwrap();
+        }
+        let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(store)
+            .with_no_client_auth();
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        let name = ServerName::try_from(name.to_string()).unwrap();
+        let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+        let mut tls = StreamOwned::new(conn, TcpStream::connect(addr).unwrap());
+        tls.conn.complete_io(&mut tls.sock).unwrap();
+        tls
+    }
+
+    fn client(
+        roots: &[&Cert],
+        addr: std::net::SocketAddr,
+        name: &str,
+    ) -> (String, ClientConnection) {
+        let mut tls = connect(roots, addr, name, &[b"http/1.1"]);
+        tls.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut resp = String::new();
+        tls.read_to_string(&mut resp).unwrap();
+        (resp, tls.conn)
+    }
+
+    #[test]
+    fn test_https_with_sni() {
+        let localhost = self_signed("localhost");
+        let other = self_signed("other.test");
+
+        let mut router = Router::new();
+        router.get("/", |_r, w| {
+            w.write_str("secure");
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .configure(HttpServer::set_tls(
+                &localhost.cert_path,
+                &localhost.key_path,
+            ))
+            .configure(HttpServer::add_tls_cert(
+                "Other.test",
+                &other.cert_path,
+                &other.key_path,
+            ))
+            .mount_route(router);
+        let handle = server.spawn().unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        let (resp, conn) = client(&[&localhost], addr, "localhost");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.ends_with("\r\n\r\nsecure"));
+        assert_eq!(conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        let (resp, conn) = client(&[&other], addr, "other.test");
+        assert!(resp.ends_with("\r\n\r\nsecure"));
+        assert_eq!(conn.peer_certificates().unwrap()[0], *other.cert.der());
+
+        // 明文请求无法完成握手
+        let mut plain = TcpStream::connect(addr).unwrap();
+        plain.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut resp = Vec::new();
+        let _ = plain.read_to_end(&mut resp);
+        assert!(!resp.starts_with(b"HTTP/1.1"));
+
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_h2_over_alpn() {
+        let cert = self_signed("h2.test");
+        let mut router = Router::new();
+        router.get("/", |r, w| {
+            w.write_str(&format!("{:?}", r.version));
+        });
+
+        for http2 in [true, false] {
+            let mut server = HttpServer::application();
+            server
+                .configure(HttpServer::set_addr("127.0.0.1:0"))
+                .configure(HttpServer::set_tls(&cert.cert_path, &cert.key_path))
+                .configure(HttpServer::set_http2(http2))
+                .mount_route(router.clone());
+            let handle = server.spawn().unwrap();
+            let tls = connect(
+                &[&cert],
+                handle.local_addr().unwrap(),
+                "h2.test",
+                &[b"h2", b"http/1.1"],
+            );
+            if http2 {
+                assert_eq!(tls.conn.alpn_protocol(), Some(&b"h2"[..]));
+                let mut client = Client::new(tls, &[]);
+                client.request(1, "GET", "/", None);
+                let (headers, body) = client.response(1);
+                assert_eq!(headers[":status"], "200");
+                assert_eq!(body, b"V2_0");
+            } else {
+                assert_eq!(tls.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+                drop(tls);
+            }
+            handle.shutdown().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_missing_cert() {
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .configure(HttpServer::set_tls("/nonexistent.crt", "/nonexistent.key"));
+        match server.spawn() {
+            Err(ServerError::Tls(msg)) => assert!(msg.contains("/nonexistent.crt")),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+}
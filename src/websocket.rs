@@ -0,0 +1,751 @@
+use std::{
+    error::Error,
+    fmt::{Debug, Display, Formatter},
+    io::{self, Cursor, ErrorKind, Read, Write},
+    net::Shutdown,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+use crate::{net::Stream, HttpRequest, HttpResponse, HttpStateCode, Version, WebSocketHandler};
+
+// RFC 6455, 1.3
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+// 控制帧的负载最多125字节(RFC 6455, 5.5)
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+const CLOSE_NORMAL: u16 = 1000;
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+const CLOSE_INVALID_DATA: u16 = 1007;
+const CLOSE_TOO_LARGE: u16 = 1009;
+
+/// WebSocket消息
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// 收到Ping时已自动回复Pong，负载最多125字节
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// 关闭帧，对方没有给出状态码时为None
+    Close(Option<CloseFrame>),
+}
+
+/// 关闭帧的状态码和原因，状态码见RFC 6455, 7.4
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// WebSocket连接出错的原因
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(io::Error),
+    /// 对方违反了协议，已发送关闭帧并关闭连接
+    Protocol(&'static str),
+    /// 消息超过`WebSocket::set_max_message_size`设置的长度，已发送关闭帧(1009)并关闭连接
+    MessageTooLarge,
+    /// 连接已关闭，或已经发送过关闭帧
+    Closed,
+}
+
+impl Display for WebSocketError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebSocketError::Io(e) => write!(f, "websocket io error: {}", e),
+            WebSocketError::Protocol(reason) => write!(f, "websocket protocol error: {}", reason),
+            WebSocketError::MessageTooLarge => write!(f, "websocket message too large"),
+            WebSocketError::Closed => write!(f, "websocket closed"),
+        }
+    }
+}
+
+impl Error for WebSocketError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WebSocketError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::UnexpectedEof => WebSocketError::Closed,
+            _ => WebSocketError::Io(e),
+        }
+    }
+}
+
+/// 握手完成后的WebSocket连接，由`Router::websocket`注册的处理函数持有
+///
+/// 分片消息会被合并为完整的消息，收到Ping时自动回复Pong，收到关闭帧时自动回复并关闭连接。
+/// WebSocket被drop时，如果还没有发送过关闭帧则发送状态码1000，然后关闭连接
+///
+/// ```no_run
+/// use httpx::{Message, Router};
+///
+/// let mut router = Router::new();
+/// router.websocket("/echo", |_r, mut ws| {
+///     while let Ok(message) = ws.recv() {
+///         if let Message::Text(_) | Message::Binary(_) = message {
+///             if ws.send(message).is_err() {
+///                 break;
+///             }
+///         }
+///     }
+/// });
+/// ```
+pub struct WebSocket {
+    inner: Arc<Inner>,
+    // 读取握手请求时多读入的数据
+    buf: Cursor<Vec<u8>>,
+    max_message_size: usize,
+    // 未接收完的分片消息
+    partial: Option<(u8, Vec<u8>)>,
+    // 已收到关闭帧或连接已断开
+    finished: bool,
+}
+
+impl Debug for WebSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WebSocket {{ max_message_size: {}, finished: {} }}",
+            self.max_message_size, self.finished
+        )
+    }
+}
+
+/// 向WebSocket连接发送消息的句柄，可以clone后传递到其他线程，见`WebSocket::sender`
+#[derive(Clone)]
+pub struct WebSocketSender {
+    inner: Arc<Inner>,
+}
+
+impl Debug for WebSocketSender {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WebSocketSender")
+    }
+}
+
+struct Inner {
+    stream: Stream,
+    // 已发送关闭帧，之后不能再发送其他消息
+    closed: Mutex<bool>,
+}
+
+impl Inner {
+    fn send(&self, message: Message) -> Result<(), WebSocketError> {
+        match message {
+            Message::Text(text) => self.write_frame(OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(OP_BINARY, &data),
+            Message::Ping(data) => self.write_frame(OP_PING, &data),
+            Message::Pong(data) => self.write_frame(OP_PONG, &data),
+            Message::Close(None) => self.write_frame(OP_CLOSE, &[]),
+            Message::Close(Some(frame)) => {
+                let mut payload = frame.code.to_be_bytes().to_vec();
+                payload.extend_from_slice(frame.reason.as_bytes());
+                self.write_frame(OP_CLOSE, &payload)
+            }
+        }
+    }
+
+    // 服务端发送的帧不使用掩码，消息不分片
+    fn write_frame(&self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+        if opcode >= OP_CLOSE && payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(WebSocketError::Io(io::Error::new(
+                ErrorKind::InvalidInput,
+                "control frame payload longer than 125 bytes",
+            )));
+        }
+        let mut closed = self.closed.lock().unwrap();
+        if *closed {
+            return Err(WebSocketError::Closed);
+        }
+        if opcode == OP_CLOSE {
+            *closed = true;
+        }
+
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+        match payload.len() {
+            len if len < 126 => frame.push(len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        let mut stream = &self.stream;
+        stream.write_all(&frame)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    fn close(&self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.to_string(),
+        })))
+    }
+}
+
+impl WebSocket {
+    pub(crate) fn new(stream: Stream, buf: Vec<u8>, max_message_size: usize) -> Self {
+        WebSocket {
+            inner: Arc::new(Inner {
+                stream,
+                closed: Mutex::new(false),
+            }),
+            buf: Cursor::new(buf),
+            max_message_size,
+            partial: None,
+            finished: false,
+        }
+    }
+
+    /// 接收下一条消息，分片消息合并后返回
+    ///
+    /// 收到关闭帧时自动回复并关闭连接，返回`Message::Close`，之后再调用返回`WebSocketError::Closed`。
+    /// 读取超时后连接状态不再可靠，应当结束处理
+    pub fn recv(&mut self) -> Result<Message, WebSocketError> {
+        if self.finished {
+            return Err(WebSocketError::Closed);
+        }
+        let result = self.read_message();
+        if let Err(WebSocketError::Closed) = result {
+            self.finished = true;
+        }
+        result
+    }
+
+    /// 发送一条消息，Ping、Pong和关闭帧的负载最多125字节
+    pub fn send(&self, message: Message) -> Result<(), WebSocketError> {
+        self.inner.send(message)
+    }
+
+    /// 发送关闭帧，之后仍需调用`recv`等待对方回复关闭帧
+    pub fn close(&self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.inner.close(code, reason)
+    }
+
+    /// 返回可以在其他线程中发送消息的句柄
+    pub fn sender(&self) -> WebSocketSender {
+        WebSocketSender {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// 设置单条消息(分片合并后)的最大长度，超出时以1009关闭连接，
+    /// 默认值与`HttpServer::set_max_body_size`相同
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// 设置`recv`的超时时间，None表示一直等待，默认值：None
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.stream.set_read_timeout(timeout)
+    }
+
+    fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let (fin, opcode, payload) = self.read_frame()?;
+            match opcode {
+                OP_PING => {
+                    // 已发送关闭帧时不再回复
+                    match self.inner.write_frame(OP_PONG, &payload) {
+                        Ok(()) | Err(WebSocketError::Closed) => {}
+                        Err(e) => return Err(e),
+                    }
+                    return Ok(Message::Ping(payload));
+                }
+                OP_PONG => return Ok(Message::Pong(payload)),
+                OP_CLOSE => return self.on_close(payload),
+                OP_CONTINUATION => match self.partial.as_mut() {
+                    Some((_, data)) => data.extend_from_slice(&payload),
+                    None => {
+                        return Err(self.fail(
+                            CLOSE_PROTOCOL_ERROR,
+                            WebSocketError::Protocol("unexpected continuation frame"),
+                        ))
+                    }
+                },
+                OP_TEXT | OP_BINARY if self.partial.is_none() => {
+                    self.partial = Some((opcode, payload))
+                }
+                OP_TEXT | OP_BINARY => {
+                    return Err(self.fail(
+                        CLOSE_PROTOCOL_ERROR,
+                        WebSocketError::Protocol("expected continuation frame"),
+                    ))
+                }
+                _ => {
+                    return Err(self.fail(
+                        CLOSE_PROTOCOL_ERROR,
+                        WebSocketError::Protocol("unknown opcode"),
+                    ))
+                }
+            }
+            if !fin {
+                continue;
+            }
+
+            let (opcode, data) = self.partial.take().unwrap_or_default();
+            if opcode == OP_BINARY {
+                return Ok(Message::Binary(data));
+            }
+            return match String::from_utf8(data) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(self.fail(
+                    CLOSE_INVALID_DATA,
+                    WebSocketError::Protocol("invalid utf-8 in text message"),
+                )),
+            };
+        }
+    }
+
+    // 读取一帧并去除掩码，返回FIN标志、opcode和负载
+    fn read_frame(&mut self) -> Result<(bool, u8, Vec<u8>), WebSocketError> {
+        let mut head = [0; 2];
+        self.read_exact(&mut head)?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0f;
+        // 没有协商扩展，RSV位必须为0
+        if head[0] & 0x70 != 0 {
+            return Err(self.fail(
+                CLOSE_PROTOCOL_ERROR,
+                WebSocketError::Protocol("reserved bits set"),
+            ));
+        }
+        // 客户端发送的帧必须使用掩码(RFC 6455, 5.1)
+        if head[1] & 0x80 == 0 {
+            return Err(self.fail(
+                CLOSE_PROTOCOL_ERROR,
+                WebSocketError::Protocol("unmasked client frame"),
+            ));
+        }
+
+        let len = match head[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                self.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if opcode >= OP_CLOSE {
+            if !fin || len > MAX_CONTROL_PAYLOAD as u64 {
+                return Err(self.fail(
+                    CLOSE_PROTOCOL_ERROR,
+                    WebSocketError::Protocol("invalid control frame"),
+                ));
+            }
+        } else {
+            let received = self.partial.as_ref().map_or(0, |(_, data)| data.len());
+            if len > (self.max_message_size.saturating_sub(received)) as u64 {
+                return Err(self.fail(CLOSE_TOO_LARGE, WebSocketError::MessageTooLarge));
+            }
+        }
+
+        let mut mask = [0; 4];
+        self.read_exact(&mut mask)?;
+        let mut payload = vec![0; len as usize];
+        self.read_exact(&mut payload)?;
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+        Ok((fin, opcode, payload))
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), WebSocketError> {
+        Read::by_ref(&mut self.buf)
+            .chain(&self.inner.stream)
+            .read_exact(buf)?;
+        Ok(())
+    }
+
+    fn on_close(&mut self, payload: Vec<u8>) -> Result<Message, WebSocketError> {
+        let frame = match payload.len() {
+            0 => None,
+            1 => {
+                return Err(self.fail(
+                    CLOSE_PROTOCOL_ERROR,
+                    WebSocketError::Protocol("invalid close frame"),
+                ))
+            }
+            _ => {
+                let code = u16::from_be_bytes([payload[0], payload[1]]);
+                // 1005、1006和1015等状态码不能出现在关闭帧中
+                if !matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999) {
+                    return Err(self.fail(
+                        CLOSE_PROTOCOL_ERROR,
+                        WebSocketError::Protocol("invalid close code"),
+                    ));
+                }
+                let reason = match String::from_utf8(payload[2..].to_vec()) {
+                    Ok(reason) => reason,
+                    Err(_) => {
+                        return Err(self.fail(
+                            CLOSE_INVALID_DATA,
+                            WebSocketError::Protocol("invalid utf-8 in close reason"),
+                        ))
+                    }
+                };
+                Some(CloseFrame { code, reason })
+            }
+        };
+
+        // 回复相同的状态码，已主动发送过关闭帧时只需关闭连接
+        let reply = match &frame {
+            Some(frame) => self.inner.close(frame.code, ""),
+            None => self.inner.send(Message::Close(None)),
+        };
+        self.finished = true;
+        let _ = self.inner.stream.shutdown(Shutdown::Both);
+        match reply {
+            Ok(()) | Err(WebSocketError::Closed) => Ok(Message::Close(frame)),
+            Err(e) => Err(e),
+        }
+    }
+
+    // 发送关闭帧并关闭连接，返回err
+    fn fail(&mut self, code: u16, err: WebSocketError) -> WebSocketError {
+        let _ = self.inner.close(code, "");
+        self.finished = true;
+        let _ = self.inner.stream.shutdown(Shutdown::Both);
+        err
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        // 已发送过关闭帧时返回Closed，对方已断开时写入失败，都可以忽略
+        let _ = self.inner.close(CLOSE_NORMAL, "");
+        let _ = self.inner.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl WebSocketSender {
+    /// 发送一条消息，与`WebSocket::send`相同
+    pub fn send(&self, message: Message) -> Result<(), WebSocketError> {
+        self.inner.send(message)
+    }
+
+    /// 发送关闭帧，与`WebSocket::close`相同
+    pub fn close(&self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.inner.close(code, reason)
+    }
+}
+
+// 握手成功后由server在独立的线程中调用处理函数
+pub(crate) struct Upgrade(WebSocketHandler);
+
+impl Debug for Upgrade {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Upgrade({:p})", &self.0)
+    }
+}
+
+impl Upgrade {
+    pub(crate) fn run(self, request: &HttpRequest, ws: WebSocket) {
+        (self.0)(request, ws)
+    }
+}
+
+// 校验握手请求(RFC 6455, 4.2.1)，成功时设置101响应并登记处理函数，失败时返回400或426
+pub(crate) fn accept(request: &HttpRequest, resp: &mut HttpResponse, handler: &WebSocketHandler) {
+    let has_token = |name: &str, token: &str| {
+        request.get_header(name).is_some_and(|v| {
+            v.split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    if !has_token("Upgrade", "websocket") {
+        resp.insert_header("Upgrade", "websocket");
+        resp.set_http_state_code(HttpStateCode::StatusUpgradeRequired);
+        return;
+    }
+    if request.get_header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        resp.insert_header("Sec-WebSocket-Version", "13");
+        resp.set_http_state_code(HttpStateCode::StatusUpgradeRequired);
+        return;
+    }
+    let key = request
+        .get_header("Sec-WebSocket-Key")
+        .map(str::trim)
+        .filter(|key| STANDARD.decode(key).is_ok_and(|key| key.len() == 16));
+    let key = match key {
+        Some(key) if request.version == Version::V1_1 && has_token("Connection", "upgrade") => key,
+        _ => {
+            resp.set_http_state_code(HttpStateCode::StatusBadRequest);
+            return;
+        }
+    };
+
+    resp.headers.remove("Content-Type");
+    resp.insert_header("Upgrade", "websocket");
+    resp.insert_header("Connection", "Upgrade");
+    resp.insert_header("Sec-WebSocket-Accept", &accept_key(key));
+    resp.set_http_state_code(HttpStateCode::StatusSwitchingProtocols);
+    resp.upgrade = Some(Upgrade(handler.clone()));
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    STANDARD.encode(sha1.finalize())
+}
+
+#[cfg(test)]
+mod test_websocket {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use super::accept_key;
+    use crate::{HttpServer, Message, Router, ServerHandle};
+
+    fn server(max_body_size: usize) -> ServerHandle {
+        let mut router = Router::new();
+        router.websocket("/echo/:name", |r, mut ws| {
+            let name = r.param("name").unwrap_or_default().to_string();
+            ws.send(Message::Text(format!("hello {}", name))).unwrap();
+            while let Ok(message) = ws.recv() {
+                if let Message::Text(_) | Message::Binary(_) = message {
+                    ws.send(message).unwrap();
+                }
+            }
+        });
+        router.websocket("/push", |_r, mut ws| {
+            let sender = ws.sender();
+            thread::spawn(move || sender.send(Message::Text("pushed".to_string())))
+                .join()
+                .unwrap()
+                .unwrap();
+            ws.close(4000, "bye").unwrap();
+            assert!(matches!(ws.recv(), Ok(Message::Close(_))));
+        });
+        let mut server = HttpServer::application();
+        server
+            .configure(HttpServer::set_addr("127.0.0.1:0"))
+            .configure(HttpServer::set_max_body_size(max_body_size))
+            .mount_route(router);
+        server.spawn().unwrap()
+    }
+
+    // 完成握手，返回连接和响应头
+    fn connect(addr: SocketAddr, request: &str) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut head = Vec::new();
+        let mut b = [0; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut b).unwrap();
+            head.push(b[0]);
+        }
+        (stream, String::from_utf8(head).unwrap())
+    }
+
+    fn handshake(addr: SocketAddr, path: &str) -> TcpStream {
+        let (stream, head) = connect(
+            addr,
+            &format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+                path
+            ),
+        );
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(!head.contains("Content-Length"));
+        stream
+    }
+
+    // 客户端发送的帧必须使用掩码
+    fn write_frame(stream: &mut TcpStream, head: u8, payload: &[u8]) {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![head];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        stream.write_all(&frame).unwrap();
+    }
+
+    fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        stream.read_exact(&mut head).unwrap();
+        assert_eq!(head[1] & 0x80, 0);
+        let len = match head[1] {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                u16::from_be_bytes(len) as usize
+            }
+            127 => {
+                let mut len = [0; 8];
+                stream.read_exact(&mut len).unwrap();
+                u64::from_be_bytes(len) as usize
+            }
+            len => len as usize,
+        };
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).unwrap();
+        (head[0], payload)
+    }
+
+    fn assert_closed(stream: &mut TcpStream, code: u16) {
+        let (head, payload) = read_frame(stream);
+        assert_eq!(head, 0x88);
+        assert_eq!(payload[..2], code.to_be_bytes());
+        assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // RFC 6455, 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_echo() {
+        let handle = server(1 << 20);
+        let mut stream = handshake(handle.local_addr().unwrap(), "/echo/rust");
+        assert_eq!(read_frame(&mut stream), (0x81, b"hello rust".to_vec()));
+
+        write_frame(&mut stream, 0x81, "你好".as_bytes());
+        assert_eq!(read_frame(&mut stream), (0x81, "你好".as_bytes().to_vec()));
+
+        // 分片消息中间插入Ping
+        write_frame(&mut stream, 0x01, b"frag");
+        write_frame(&mut stream, 0x89, b"ping");
+        write_frame(&mut stream, 0x80, b"ment");
+        assert_eq!(read_frame(&mut stream), (0x8a, b"ping".to_vec()));
+        assert_eq!(read_frame(&mut stream), (0x81, b"fragment".to_vec()));
+
+        let data: Vec<u8> = (0..70000).map(|i| i as u8).collect();
+        write_frame(&mut stream, 0x82, &data);
+        assert_eq!(read_frame(&mut stream), (0x82, data));
+
+        write_frame(&mut stream, 0x88, &1000u16.to_be_bytes());
+        assert_closed(&mut stream, 1000);
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_server_close() {
+        let handle = server(1 << 20);
+        let mut stream = handshake(handle.local_addr().unwrap(), "/push");
+        assert_eq!(read_frame(&mut stream), (0x81, b"pushed".to_vec()));
+        let (head, payload) = read_frame(&mut stream);
+        assert_eq!(head, 0x88);
+        assert_eq!(payload, b"\x0f\xa0bye");
+        write_frame(&mut stream, 0x88, &payload[..2]);
+        assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+        // 停止server时关闭仍在处理中的WebSocket连接
+        let mut stream = handshake(handle.local_addr().unwrap(), "/echo/rust");
+        read_frame(&mut stream);
+        handle.shutdown().unwrap();
+        assert_closed(&mut stream, 1000);
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let handle = server(16);
+        let addr = handle.local_addr().unwrap();
+
+        let mut stream = handshake(addr, "/echo/a");
+        read_frame(&mut stream);
+        stream.write_all(&[0x81, 0x02, b'h', b'i']).unwrap();
+        assert_closed(&mut stream, 1002);
+
+        let mut stream = handshake(addr, "/echo/a");
+        read_frame(&mut stream);
+        write_frame(&mut stream, 0x01, &[b'x'; 10]);
+        write_frame(&mut stream, 0x80, &[b'x'; 10]);
+        assert_closed(&mut stream, 1009);
+
+        let mut stream = handshake(addr, "/echo/a");
+        read_frame(&mut stream);
+        write_frame(&mut stream, 0x81, &[0xff, 0xfe]);
+        assert_closed(&mut stream, 1007);
+
+        let mut stream = handshake(addr, "/echo/a");
+        read_frame(&mut stream);
+        write_frame(&mut stream, 0x80, b"x");
+        assert_closed(&mut stream, 1002);
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_handshake_rejected() {
+        let handle = server(16);
+        let addr = handle.local_addr().unwrap();
+
+        let (_, head) = connect(addr, "GET /echo/a HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(head.contains("Upgrade: websocket\r\n"));
+
+        let (_, head) = connect(
+            addr,
+            "GET /echo/a HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n",
+        );
+        assert!(head.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(head.contains("Sec-WebSocket-Version: 13\r\n"));
+
+        let (_, head) = connect(
+            addr,
+            "GET /echo/a HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: short\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        );
+        assert!(head.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let (_, head) = connect(addr, "POST /echo/a HTTP/1.1\r\nContent-Length: 0\r\n\r\n");
+        assert!(head.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        handle.shutdown().unwrap();
+    }
+}